@@ -0,0 +1,90 @@
+//! Times single opcode handlers of the shift add engine on synthetic programs,
+//! so a change to the execute loop can be pinned on a handler, and the same
+//! programs on the sparse tape.
+//!
+//! `cargo bench --bench handlers`
+
+use bf_interpreter::shift_add_engine::Opcode::{self, *};
+use bf_interpreter::*;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 200;
+
+/// Runs `opcodes` on `E` `RUNS` times, calling `setup` on the tape before each
+/// run, and prints the median time per run and per `ops` handler calls.
+fn bench<E: BfEngine<OPCODE = Opcode>>(
+    name: &str,
+    opcodes: &[Opcode],
+    ops: u32,
+    setup: impl Fn(&mut [u8; DATA_LEN]),
+) {
+    let config = RunConfig::default();
+    let mut data = new_tape(&config);
+    let mut durations: Vec<Duration> = (0..RUNS)
+        .map(|_| {
+            setup(&mut data);
+            let start = Instant::now();
+            black_box(E::execute(
+                black_box(opcodes),
+                black_box(&mut data),
+                &mut std::io::empty(),
+                &mut std::io::sink(),
+                &config,
+            ));
+            start.elapsed()
+        })
+        .collect();
+    durations.sort();
+    let median = durations[RUNS / 2];
+    let per_op = median.as_nanos() as f64 / ops as f64;
+    println!("{name:<24} {median:>12?} {per_op:>8.2}ns/op");
+}
+
+fn main() {
+    // A run of nonzero cells for `Seek` to walk over.
+    for cells in [16, 1024, 60000] {
+        bench::<ShiftAddEngine>(
+            &format!("seek {cells} cells"),
+            &[Seek(1), Exit],
+            cells,
+            |data| {
+                data[..cells as usize].fill(1);
+                data[cells as usize] = 0;
+            },
+        );
+    }
+
+    // The same `AddTo` over and over, each on a fresh cell.
+    let add_tos: Vec<Opcode> = (0..10_000)
+        .flat_map(|_| [AddTo(1), AddRight(0, 2)])
+        .chain([Exit])
+        .collect();
+    bench::<ShiftAddEngine>("add_to", &add_tos, 10_000, |data| {
+        for cell in data.iter_mut().step_by(2) {
+            *cell = 7;
+        }
+    });
+
+    // `[-]` kept as a loop, so each iteration is a branch and an add.
+    let branch = [BranchZero(2), AddRight(255, 0), BranchNotZero(0), Exit];
+    bench::<ShiftAddEngine>("branch loop 255", &branch, 255, |data| data[0] = 255);
+
+    let adds: Vec<Opcode> = (0..10_000).map(|_| AddRight(3, 1)).chain([Exit]).collect();
+    bench::<ShiftAddEngine>("add_right", &adds, 10_000, |_| ());
+
+    // The same programs on both tapes: loops nested three deep over a few
+    // cells, counted in iterations of the middle one, and a write to every
+    // 16th cell across the whole tape.
+    let nested = "++++++++[>++++++++[>++++++++[>+<-]<-]<-]";
+    let nested = compile_from_str::<ShiftAddEngine>(nested).unwrap();
+    let spread: Vec<Opcode> = (0..DATA_LEN / 16)
+        .map(|_| AddRight(1, 16))
+        .chain([Exit])
+        .collect();
+    let reset = |data: &mut [u8; DATA_LEN]| data.fill(0);
+    bench::<ShiftAddEngine>("nested loops dense", &nested, 64, reset);
+    bench::<SparseEngine>("nested loops sparse", &nested, 64, reset);
+    bench::<ShiftAddEngine>("spread dense", &spread, DATA_LEN as u32 / 16, reset);
+    bench::<SparseEngine>("spread sparse", &spread, DATA_LEN as u32 / 16, reset);
+}
@@ -0,0 +1,6386 @@
+use std::io::Read;
+use std::io::Write;
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum BasicOpcode {
+    Add,
+    Sub,
+    Open,
+    Close,
+    Right,
+    Left,
+    Dot,
+    Comma,
+    /// `@` in Extended Type I: end the program.
+    End,
+    /// `$` in Extended Type I: copy the cell into the register.
+    Store,
+    /// `!` in Extended Type I: copy the register into the cell.
+    Restore,
+    /// `}` in Extended Type I: shift the cell's bits right by one.
+    ShiftBitsRight,
+    /// `{` in Extended Type I: shift the cell's bits left by one.
+    ShiftBitsLeft,
+}
+
+pub const DATA_LEN: usize = 1 << 16;
+
+pub fn to_basic_opcode(c: u8) -> Option<BasicOpcode> {
+    BasicOpcode::try_from(c).ok()
+}
+
+pub fn from_basic_opcode(opcode: BasicOpcode) -> u8 {
+    opcode.to_byte()
+}
+
+impl BasicOpcode {
+    /// The source character for this command.
+    pub fn to_byte(self) -> u8 {
+        match self {
+            BasicOpcode::Add => b'+',
+            BasicOpcode::Sub => b'-',
+            BasicOpcode::Right => b'>',
+            BasicOpcode::Left => b'<',
+            BasicOpcode::Open => b'[',
+            BasicOpcode::Close => b']',
+            BasicOpcode::Dot => b'.',
+            BasicOpcode::Comma => b',',
+            BasicOpcode::End => b'@',
+            BasicOpcode::Store => b'$',
+            BasicOpcode::Restore => b'!',
+            BasicOpcode::ShiftBitsRight => b'}',
+            BasicOpcode::ShiftBitsLeft => b'{',
+        }
+    }
+}
+
+/// Which bytes of a source file are commands.
+pub trait Dialect {
+    const NAME: &'static str;
+    fn command(c: u8) -> Option<BasicOpcode>;
+}
+
+/// The eight commands, and nothing else.
+pub struct Brainfuck;
+impl Dialect for Brainfuck {
+    const NAME: &'static str = "Brainfuck";
+    fn command(c: u8) -> Option<BasicOpcode> {
+        BasicOpcode::try_from(c).ok()
+    }
+}
+
+/// Brainfuck plus `@`, `$`, `!`, `}` and `{`. Only the Raw engine runs these.
+pub struct ExtendedTypeI;
+impl Dialect for ExtendedTypeI {
+    const NAME: &'static str = "Extended Type I";
+    fn command(c: u8) -> Option<BasicOpcode> {
+        match c {
+            b'@' => Some(BasicOpcode::End),
+            b'$' => Some(BasicOpcode::Store),
+            b'!' => Some(BasicOpcode::Restore),
+            b'}' => Some(BasicOpcode::ShiftBitsRight),
+            b'{' => Some(BasicOpcode::ShiftBitsLeft),
+            _ => Brainfuck::command(c),
+        }
+    }
+}
+
+/// What engines other than Raw return for Extended Type I commands.
+const EXTENDED_UNSUPPORTED: &str = "Extended Type I commands need the Raw engine";
+
+impl TryFrom<u8> for BasicOpcode {
+    type Error = &'static str;
+    fn try_from(c: u8) -> Result<Self, Self::Error> {
+        match c {
+            b'+' => Ok(BasicOpcode::Add),
+            b'-' => Ok(BasicOpcode::Sub),
+            b'>' => Ok(BasicOpcode::Right),
+            b'<' => Ok(BasicOpcode::Left),
+            b'[' => Ok(BasicOpcode::Open),
+            b']' => Ok(BasicOpcode::Close),
+            b'.' => Ok(BasicOpcode::Dot),
+            b',' => Ok(BasicOpcode::Comma),
+            _ => Err("not a Brainfuck command"),
+        }
+    }
+}
+
+impl From<BasicOpcode> for u8 {
+    fn from(opcode: BasicOpcode) -> u8 {
+        opcode.to_byte()
+    }
+}
+
+/// Random programs for testing the engines against each other.
+pub mod random_program {
+    use super::BasicOpcode;
+
+    /// Returns `len` opcodes with balanced brackets, the same ones for the same
+    /// `seed`.
+    pub fn generate(seed: u64, len: usize) -> Vec<BasicOpcode> {
+        use BasicOpcode::*;
+        const OPCODES: [BasicOpcode; 8] = [Add, Sub, Right, Left, Dot, Comma, Open, Close];
+        let mut state = seed;
+        let mut program = Vec::with_capacity(len);
+        let mut depth = 0;
+        while program.len() < len {
+            let remaining = len - program.len();
+            // Leave room to close everything that is open.
+            let opcode = if remaining == depth {
+                Close
+            } else {
+                OPCODES[(splitmix64(&mut state) % OPCODES.len() as u64) as usize]
+            };
+            match opcode {
+                Open if remaining < depth + 2 => continue,
+                Open => depth += 1,
+                Close if depth == 0 => continue,
+                Close => depth -= 1,
+                _ => (),
+            }
+            program.push(opcode);
+        }
+        program
+    }
+
+    fn splitmix64(state: &mut u64) -> u64 {
+        *state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = *state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Warnings about programs that run fine but probably don't do what was meant.
+pub mod lint {
+    use super::BasicOpcode;
+
+    /// Indices of `,` commands with no `.` before them in the same basic block,
+    /// which tends to be a missing prompt. Only the first in each block counts.
+    pub fn unprompted_reads(opcodes: &[BasicOpcode]) -> Vec<usize> {
+        let mut reads = Vec::new();
+        let mut prompted = false;
+        let mut reported = false;
+        for (i, opcode) in opcodes.iter().enumerate() {
+            match opcode {
+                BasicOpcode::Open | BasicOpcode::Close => {
+                    prompted = false;
+                    reported = false;
+                }
+                BasicOpcode::Dot => prompted = true,
+                BasicOpcode::Comma if !prompted && !reported => {
+                    reads.push(i);
+                    reported = true;
+                }
+                _ => (),
+            }
+        }
+        reads
+    }
+}
+
+pub trait BfEngine {
+    const NAME: &'static str;
+    /// One line on what the engine is for.
+    const DESCRIPTION: &'static str;
+    /// Short names of the rewrites `generate` applies, in no particular order.
+    const OPTIMIZATIONS: &'static [&'static str];
+    type OPCODE: std::fmt::Debug;
+    fn generate(iter: impl Iterator<Item = BasicOpcode>)
+        -> Result<Vec<Self::OPCODE>, &'static str>;
+    /// Runs on the caller's tape without clearing it first, so one tape can be
+    /// reused across runs by refilling it with `config.tape_fill` in between.
+    ///
+    /// Output goes to `output` as it's produced, so a program that never halts
+    /// still streams; when it becomes visible is up to `output`'s buffering.
+    fn execute(
+        opcodes: &[Self::OPCODE],
+        data: &mut [u8; DATA_LEN],
+        input: &mut impl Read,
+        output: &mut impl Write,
+        config: &RunConfig,
+    ) -> ExitStatus;
+    /// `execute` on a fresh tape from [`new_tape`], which is returned too.
+    fn execute_fresh(
+        opcodes: &[Self::OPCODE],
+        input: &mut impl Read,
+        output: &mut impl Write,
+        config: &RunConfig,
+    ) -> (ExitStatus, Box<[u8; DATA_LEN]>) {
+        let mut data = new_tape(config);
+        let status = Self::execute(opcodes, &mut data, input, output, config);
+        (status, data)
+    }
+    /// Like `execute`, reporting what happens to `observer` and stopping when
+    /// its `pause` says so. Limits and interrupts are built on this, so there
+    /// is no default that skips the observer.
+    fn execute_observed(
+        opcodes: &[Self::OPCODE],
+        data: &mut [u8; DATA_LEN],
+        input: &mut impl Read,
+        output: &mut impl Write,
+        config: &RunConfig,
+        observer: &mut impl Observer,
+    ) -> ExitStatus;
+}
+
+/// An opcode that may begin or end a loop, so listings can indent by depth.
+pub trait LoopOpcode {
+    fn opens_loop(&self) -> bool;
+    fn closes_loop(&self) -> bool;
+}
+
+/// What listings put in front of an opcode for each enclosing loop.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Indent {
+    Spaces(usize),
+    Tab,
+}
+
+impl Default for Indent {
+    fn default() -> Self {
+        Indent::Spaces(4)
+    }
+}
+
+impl Indent {
+    /// The indentation for `depth` enclosing loops.
+    pub fn at(self, depth: usize) -> String {
+        match self {
+            Indent::Spaces(width) => " ".repeat(width * depth),
+            Indent::Tab => "\t".repeat(depth),
+        }
+    }
+}
+
+/// Displays compiled opcodes one per line, numbered and indented by loop depth.
+pub struct Program<'a, T> {
+    pub opcodes: &'a [T],
+    pub indent: Indent,
+}
+
+impl<'a, T> Program<'a, T> {
+    pub fn new(opcodes: &'a [T]) -> Self {
+        Program {
+            opcodes,
+            indent: Indent::default(),
+        }
+    }
+    pub fn indent(self, indent: Indent) -> Self {
+        Program { indent, ..self }
+    }
+}
+
+impl<T: LoopOpcode + std::fmt::Debug> std::fmt::Display for Program<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut depth: usize = 0;
+        for (pc, opcode) in self.opcodes.iter().enumerate() {
+            if opcode.closes_loop() {
+                depth = depth.saturating_sub(1);
+            }
+            writeln!(f, "{pc:>5}: {}{opcode:?}", self.indent.at(depth))?;
+            if opcode.opens_loop() {
+                depth += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Why a program could not be compiled.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct CompileError {
+    pub message: &'static str,
+    /// Byte offset in the source of the bracket at fault, when known.
+    pub offset: Option<usize>,
+}
+
+impl From<&'static str> for CompileError {
+    fn from(message: &'static str) -> Self {
+        Self {
+            message,
+            offset: None,
+        }
+    }
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.message)?;
+        if let Some(offset) = self.offset {
+            write!(f, " at byte {offset}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+impl CompileError {
+    /// Displays the error followed by the source around `offset`, with a caret
+    /// under the offending byte.
+    pub fn with_source<'a>(&'a self, src: &'a [u8]) -> WithSource<'a> {
+        WithSource { error: self, src }
+    }
+}
+
+pub struct WithSource<'a> {
+    error: &'a CompileError,
+    src: &'a [u8],
+}
+
+impl std::fmt::Display for WithSource<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const CONTEXT: usize = 20;
+        write!(f, "{}", self.error)?;
+        let Some(offset) = self.error.offset.filter(|&offset| offset < self.src.len()) else {
+            return Ok(());
+        };
+        let start = offset.saturating_sub(CONTEXT);
+        let end = (offset + CONTEXT + 1).min(self.src.len());
+        // One column per byte, so the caret lines up.
+        let snippet: String = self.src[start..end]
+            .iter()
+            .map(|&b| if b.is_ascii_graphic() { b as char } else { ' ' })
+            .collect();
+        write!(
+            f,
+            "\n    {snippet}\n    {:>width$}",
+            "^",
+            width = offset - start + 1
+        )
+    }
+}
+
+/// Deepest loop nesting [`check_brackets`] accepts, far beyond any real program.
+pub const MAX_NESTING: usize = 1 << 16;
+
+/// Finds the first `]` without a `[`, or else the last `[` without a `]`.
+pub fn check_brackets(src: &[u8]) -> Result<(), CompileError> {
+    check_brackets_nested(src, MAX_NESTING)
+}
+
+/// [`check_brackets`], also failing on the first `[` nested more than
+/// `max_nesting` loops deep.
+pub fn check_brackets_nested(src: &[u8], max_nesting: usize) -> Result<(), CompileError> {
+    let mut open_stack = Vec::new();
+    for (offset, &b) in src.iter().enumerate() {
+        match b {
+            b'[' if open_stack.len() >= max_nesting => {
+                return Err(CompileError {
+                    message: "loops nested too deep",
+                    offset: Some(offset),
+                })
+            }
+            b'[' => open_stack.push(offset),
+            b']' if open_stack.pop().is_none() => {
+                return Err(CompileError {
+                    message: "unbalanced brackets: extra ]",
+                    offset: Some(offset),
+                })
+            }
+            _ => (),
+        }
+    }
+    match open_stack.pop() {
+        Some(offset) => Err(CompileError {
+            message: "unbalanced brackets: extra [",
+            offset: Some(offset),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Compiles Brainfuck source held in memory, skipping every non-command byte.
+pub fn compile_from_bytes<E: BfEngine>(src: &[u8]) -> Result<Vec<E::OPCODE>, CompileError> {
+    check_brackets(src)?;
+    Ok(E::generate(
+        src.iter().copied().filter_map(to_basic_opcode),
+    )?)
+}
+
+pub fn compile_from_str<E: BfEngine>(src: &str) -> Result<Vec<E::OPCODE>, CompileError> {
+    compile_from_bytes::<E>(src.as_bytes())
+}
+
+/// Hooks into an engine's dispatch loop. Every hook defaults to doing nothing,
+/// so the plain `execute` path pays nothing for them.
+pub trait Observer {
+    /// The opcode at `pc` is about to run.
+    #[inline(always)]
+    fn opcode(&mut self, _pc: usize) {}
+    /// The cell at `dp` was written with `value`.
+    #[inline(always)]
+    fn write(&mut self, _dp: usize, _value: u8) {}
+    /// The data pointer moved to `dp`.
+    #[inline(always)]
+    fn moved(&mut self, _dp: usize) {}
+    /// `byte` was written to the output.
+    #[inline(always)]
+    fn output(&mut self, _byte: u8) {}
+    /// The cell at `dp` wrapped past 255 or below 0 on an add or subtract.
+    #[inline(always)]
+    fn wrapped(&mut self, _dp: usize) {}
+    /// Called with the tape and data pointer after every opcode, returning
+    /// `true` stops execution before the program does.
+    #[inline(always)]
+    fn pause(&mut self, _data: &[u8; DATA_LEN], _dp: usize) -> bool {
+        false
+    }
+    /// What the run ends with after `pause` stopped it.
+    #[inline(always)]
+    fn stop_status(&self) -> ExitStatus {
+        ExitStatus::Interrupted
+    }
+}
+
+impl Observer for () {}
+
+impl<O: Observer> Observer for &mut O {
+    #[inline(always)]
+    fn opcode(&mut self, pc: usize) {
+        (**self).opcode(pc)
+    }
+    #[inline(always)]
+    fn write(&mut self, dp: usize, value: u8) {
+        (**self).write(dp, value)
+    }
+    #[inline(always)]
+    fn moved(&mut self, dp: usize) {
+        (**self).moved(dp)
+    }
+    #[inline(always)]
+    fn output(&mut self, byte: u8) {
+        (**self).output(byte)
+    }
+    #[inline(always)]
+    fn wrapped(&mut self, dp: usize) {
+        (**self).wrapped(dp)
+    }
+    #[inline(always)]
+    fn pause(&mut self, data: &[u8; DATA_LEN], dp: usize) -> bool {
+        (**self).pause(data, dp)
+    }
+    #[inline(always)]
+    fn stop_status(&self) -> ExitStatus {
+        (**self).stop_status()
+    }
+}
+
+/// Lets optional observers be combined without a separate code path for each.
+impl<O: Observer> Observer for Option<O> {
+    #[inline(always)]
+    fn opcode(&mut self, pc: usize) {
+        if let Some(o) = self {
+            o.opcode(pc)
+        }
+    }
+    #[inline(always)]
+    fn write(&mut self, dp: usize, value: u8) {
+        if let Some(o) = self {
+            o.write(dp, value)
+        }
+    }
+    #[inline(always)]
+    fn moved(&mut self, dp: usize) {
+        if let Some(o) = self {
+            o.moved(dp)
+        }
+    }
+    #[inline(always)]
+    fn output(&mut self, byte: u8) {
+        if let Some(o) = self {
+            o.output(byte)
+        }
+    }
+    #[inline(always)]
+    fn wrapped(&mut self, dp: usize) {
+        if let Some(o) = self {
+            o.wrapped(dp)
+        }
+    }
+    #[inline(always)]
+    fn pause(&mut self, data: &[u8; DATA_LEN], dp: usize) -> bool {
+        self.as_mut().is_some_and(|o| o.pause(data, dp))
+    }
+    #[inline(always)]
+    fn stop_status(&self) -> ExitStatus {
+        self.as_ref()
+            .map_or(ExitStatus::Interrupted, |o| o.stop_status())
+    }
+}
+
+impl<A: Observer, B: Observer> Observer for (A, B) {
+    #[inline(always)]
+    fn opcode(&mut self, pc: usize) {
+        self.0.opcode(pc);
+        self.1.opcode(pc);
+    }
+    #[inline(always)]
+    fn write(&mut self, dp: usize, value: u8) {
+        self.0.write(dp, value);
+        self.1.write(dp, value);
+    }
+    #[inline(always)]
+    fn moved(&mut self, dp: usize) {
+        self.0.moved(dp);
+        self.1.moved(dp);
+    }
+    #[inline(always)]
+    fn output(&mut self, byte: u8) {
+        self.0.output(byte);
+        self.1.output(byte);
+    }
+    #[inline(always)]
+    fn wrapped(&mut self, dp: usize) {
+        self.0.wrapped(dp);
+        self.1.wrapped(dp);
+    }
+    #[inline(always)]
+    fn pause(&mut self, data: &[u8; DATA_LEN], dp: usize) -> bool {
+        // `|` rather than `||` so both sides see every opcode.
+        self.0.pause(data, dp) | self.1.pause(data, dp)
+    }
+    #[inline(always)]
+    fn stop_status(&self) -> ExitStatus {
+        match self.0.stop_status() {
+            ExitStatus::Interrupted => self.1.stop_status(),
+            status => status,
+        }
+    }
+}
+
+/// How many times each opcode ran, indexed by pc.
+#[derive(Clone, Default, Debug)]
+pub struct Profile {
+    pub counts: Vec<usize>,
+    /// Roughly how much work each opcode did: one per run plus one per cell it
+    /// wrote or moved over, so a `Seek` across 10000 cells costs 10001.
+    pub costs: Vec<usize>,
+    pc: usize,
+}
+
+impl Profile {
+    /// `{"counts":[...]}`, readable by `from_json`.
+    pub fn to_json(&self) -> String {
+        let counts: Vec<String> = self.counts.iter().map(usize::to_string).collect();
+        format!("{{\"counts\":[{}]}}", counts.join(","))
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, &'static str> {
+        let counts = json
+            .split_once("\"counts\"")
+            .and_then(|(_, rest)| rest.split_once('['))
+            .and_then(|(_, rest)| rest.split_once(']'))
+            .ok_or("expected a profile like {\"counts\":[...]}")?
+            .0;
+        let counts = counts
+            .split(',')
+            .map(str::trim)
+            .filter(|count| !count.is_empty())
+            .map(|count| count.parse().map_err(|_| "invalid count in profile"))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            counts,
+            ..Self::default()
+        })
+    }
+
+    /// A profile with room for `len` opcodes, so a run doesn't have to grow it.
+    pub fn with_len(len: usize) -> Self {
+        Self {
+            counts: vec![0; len],
+            costs: vec![0; len],
+            pc: 0,
+        }
+    }
+
+    /// Zeroes every count and cost, keeping the buffers for the next run.
+    pub fn reset(&mut self) {
+        self.counts.fill(0);
+        self.costs.fill(0);
+        self.pc = 0;
+    }
+
+    /// Bytes allocated for the counts and costs.
+    pub fn heap_bytes(&self) -> usize {
+        (self.counts.capacity() + self.costs.capacity()) * std::mem::size_of::<usize>()
+    }
+
+    /// How much each pc's count changed going from `self` to `other`, skipping
+    /// unchanged ones.
+    pub fn diff(&self, other: &Profile) -> Vec<(usize, i64)> {
+        let count = |profile: &Profile, pc| *profile.counts.get(pc).unwrap_or(&0) as i64;
+        (0..self.counts.len().max(other.counts.len()))
+            .map(|pc| (pc, count(other, pc) - count(self, pc)))
+            .filter(|&(_, delta)| delta != 0)
+            .collect()
+    }
+}
+
+impl Observer for Profile {
+    fn opcode(&mut self, pc: usize) {
+        if pc >= self.counts.len() {
+            self.counts.resize(pc + 1, 0);
+            self.costs.resize(pc + 1, 0);
+        }
+        self.counts[pc] += 1;
+        self.costs[pc] += 1;
+        self.pc = pc;
+    }
+    fn write(&mut self, _dp: usize, _value: u8) {
+        if let Some(cost) = self.costs.get_mut(self.pc) {
+            *cost += 1;
+        }
+    }
+    fn moved(&mut self, _dp: usize) {
+        if let Some(cost) = self.costs.get_mut(self.pc) {
+            *cost += 1;
+        }
+    }
+}
+
+/// Summary of which parts of the tape a run touched.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct RunStats {
+    /// Highest cell the program moved to or wrote, so cells `0..=max_dp` are all
+    /// it needs.
+    pub max_dp: usize,
+}
+
+impl Observer for RunStats {
+    fn write(&mut self, dp: usize, _value: u8) {
+        self.max_dp = self.max_dp.max(dp);
+    }
+    fn moved(&mut self, dp: usize) {
+        self.max_dp = self.max_dp.max(dp);
+    }
+}
+
+/// Prints a warning to stderr each time a cell wraps around, up to `limit` of
+/// them, since an unintended wrap is a common bug when porting a program.
+#[derive(Clone, Debug)]
+pub struct WrapWarnings {
+    pub limit: usize,
+    /// How many wraps happened, including ones past `limit`.
+    pub count: usize,
+    pc: usize,
+}
+
+impl WrapWarnings {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            count: 0,
+            pc: 0,
+        }
+    }
+}
+
+impl Observer for WrapWarnings {
+    fn opcode(&mut self, pc: usize) {
+        self.pc = pc;
+    }
+    fn wrapped(&mut self, dp: usize) {
+        if self.count < self.limit {
+            eprintln!("warning: cell {dp} wrapped around at pc {}", self.pc);
+        } else if self.count == self.limit {
+            eprintln!("warning: not showing any more wraps");
+        }
+        self.count += 1;
+    }
+}
+
+/// Stops a run that has gone `limit` opcodes without output, for programs that
+/// print everything interesting early and then loop forever.
+#[derive(Copy, Clone, Debug)]
+pub struct Quiescence {
+    pub limit: u64,
+    since_output: u64,
+}
+
+impl Quiescence {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            since_output: 0,
+        }
+    }
+}
+
+impl Observer for Quiescence {
+    fn opcode(&mut self, _pc: usize) {
+        self.since_output += 1;
+    }
+    fn output(&mut self, _byte: u8) {
+        self.since_output = 0;
+    }
+    fn pause(&mut self, _data: &[u8; DATA_LEN], _dp: usize) -> bool {
+        self.since_output > self.limit
+    }
+    fn stop_status(&self) -> ExitStatus {
+        if self.since_output > self.limit {
+            ExitStatus::Quiescent
+        } else {
+            ExitStatus::Interrupted
+        }
+    }
+}
+
+/// Calls `callback` with the running count every `interval` ticks. As an
+/// [`Observer`] it ticks once per opcode executed.
+pub struct Progress<F: FnMut(u64)> {
+    interval: u64,
+    count: u64,
+    callback: F,
+}
+
+impl<F: FnMut(u64)> Progress<F> {
+    pub fn new(interval: u64, callback: F) -> Self {
+        Self {
+            interval,
+            count: 0,
+            callback,
+        }
+    }
+
+    #[inline(always)]
+    pub fn tick(&mut self) {
+        self.count += 1;
+        if self.count.is_multiple_of(self.interval) {
+            (self.callback)(self.count);
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+impl<F: FnMut(u64)> Observer for Progress<F> {
+    #[inline(always)]
+    fn opcode(&mut self, _pc: usize) {
+        self.tick();
+    }
+}
+
+/// What a tape cell holds, for [`RawEngine::execute_cells`]. Every other
+/// engine uses `u8`.
+///
+/// Adds wrap, loops only test for zero and `.` writes the same byte either
+/// way, so signed and unsigned cells differ only in `}` from Extended Type I,
+/// which keeps a signed cell's sign.
+pub trait Cell: Copy + Eq + std::fmt::Debug {
+    fn from_byte(byte: u8) -> Self;
+    fn to_byte(self) -> u8;
+    fn shift_right(self) -> Self;
+    /// The tape with every cell as its byte, for [`Observer::pause`].
+    fn bytes(tape: &[Self; DATA_LEN]) -> &[u8; DATA_LEN];
+}
+
+impl Cell for u8 {
+    fn from_byte(byte: u8) -> Self {
+        byte
+    }
+    fn to_byte(self) -> u8 {
+        self
+    }
+    fn shift_right(self) -> Self {
+        self >> 1
+    }
+    fn bytes(tape: &[Self; DATA_LEN]) -> &[u8; DATA_LEN] {
+        tape
+    }
+}
+
+impl Cell for i8 {
+    fn from_byte(byte: u8) -> Self {
+        byte as i8
+    }
+    fn to_byte(self) -> u8 {
+        self as u8
+    }
+    fn shift_right(self) -> Self {
+        self >> 1
+    }
+    fn bytes(tape: &[Self; DATA_LEN]) -> &[u8; DATA_LEN] {
+        // SAFETY: `i8` and `u8` have the same size and alignment, and every
+        // bit pattern is valid for both.
+        unsafe { &*(tape as *const [i8; DATA_LEN] as *const [u8; DATA_LEN]) }
+    }
+}
+
+/// Cells are `u8`, see [`Cell`] for signed ones.
+pub fn new_tape(config: &RunConfig) -> Box<[u8; DATA_LEN]> {
+    (0..DATA_LEN)
+        .map(|_| config.tape_fill)
+        .collect::<Vec<_>>()
+        .into_boxed_slice()
+        .try_into()
+        .unwrap()
+}
+
+/// Runs `opcodes` once per input on a pool of threads, each with its own tape,
+/// and returns every run's status and output in the order of `inputs`. Uses
+/// scoped std threads rather than rayon, so it needs no feature.
+pub fn run_parallel<E: BfEngine>(
+    opcodes: &[E::OPCODE],
+    inputs: &[&[u8]],
+    config: &RunConfig,
+) -> Vec<(ExitStatus, Vec<u8>)>
+where
+    E::OPCODE: Sync,
+{
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_len = inputs.len().div_ceil(threads).max(1);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = inputs
+            .chunks(chunk_len)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    let mut data = new_tape(config);
+                    let mut results = Vec::with_capacity(chunk.len());
+                    for input in chunk {
+                        data.fill(config.tape_fill);
+                        let mut output = Vec::new();
+                        let status =
+                            E::execute(opcodes, &mut data, &mut &input[..], &mut output, config);
+                        results.push((status, output));
+                    }
+                    results
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    })
+}
+
+/// Reads one byte of program input, `None` at the end of it.
+pub fn read_byte(input: &mut impl Read) -> Option<u8> {
+    let mut byte = [0];
+    loop {
+        match input.read(&mut byte) {
+            Ok(0) => return None,
+            Ok(_) => return Some(byte[0]),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => return None,
+        }
+    }
+}
+
+/// Why execution stopped.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ExitStatus {
+    /// Ran off the end of the program (or out of input).
+    Halted,
+    /// An [`Observer`] stopped execution before the program finished.
+    Interrupted,
+    /// A [`Quiescence`] observer saw no output for too long.
+    Quiescent,
+    /// With `strict_tape`, a pointer move from `dp` would have landed on `attempted`,
+    /// which is outside `0..DATA_LEN`.
+    PointerOutOfBounds { dp: usize, attempted: isize },
+    /// A `Seek` moved `max_seek` times without finding a zero.
+    SeekLimit,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct RunConfig {
+    /// Stop with `ExitStatus::PointerOutOfBounds` instead of wrapping around the tape.
+    ///
+    /// Only [`RawEngine`] checks every `<` and `>`. The other engines merge
+    /// runs of them and check where each run ends: `<>` at cell 0 passes, and
+    /// `attempted` is where the whole run would have landed.
+    pub strict_tape: bool,
+    /// Value every cell starts out with.
+    pub tape_fill: u8,
+    /// Most cells a single `Seek` moves over before stopping with
+    /// `ExitStatus::SeekLimit`, so a scan of a tape without zeros ends.
+    pub max_seek: usize,
+}
+
+impl Default for RunConfig {
+    fn default() -> Self {
+        Self {
+            strict_tape: false,
+            tape_fill: 0,
+            max_seek: DATA_LEN,
+        }
+    }
+}
+
+impl RunConfig {
+    pub fn strict_tape(mut self, strict_tape: bool) -> Self {
+        self.strict_tape = strict_tape;
+        self
+    }
+
+    pub fn tape_fill(mut self, tape_fill: u8) -> Self {
+        self.tape_fill = tape_fill;
+        self
+    }
+
+    pub fn max_seek(mut self, max_seek: usize) -> Self {
+        self.max_seek = max_seek;
+        self
+    }
+}
+
+/// Moves `dp` by `shift`, wrapping around the tape unless `strict_tape` is set.
+#[inline(always)]
+pub fn shift_pointer(dp: usize, shift: isize, config: &RunConfig) -> Result<usize, ExitStatus> {
+    let attempted = dp as isize + shift;
+    if config.strict_tape && !(0..DATA_LEN as isize).contains(&attempted) {
+        Err(ExitStatus::PointerOutOfBounds { dp, attempted })
+    } else {
+        Ok(attempted.rem_euclid(DATA_LEN as isize) as usize)
+    }
+}
+
+pub use hex_dump::HexDump;
+pub mod hex_dump {
+    use std::io::{self, Write};
+
+    const LINE_LEN: usize = 16;
+
+    /// Renders everything written to it as a `hexdump -C` style listing:
+    /// an offset, 16 hex bytes and their printable ASCII per line.
+    ///
+    /// The last partial line is written when the `HexDump` is dropped.
+    pub struct HexDump<W: Write> {
+        inner: W,
+        line: Vec<u8>,
+        offset: usize,
+    }
+
+    impl<W: Write> HexDump<W> {
+        pub fn new(inner: W) -> Self {
+            Self {
+                inner,
+                line: Vec::with_capacity(LINE_LEN),
+                offset: 0,
+            }
+        }
+
+        fn write_line(&mut self) -> io::Result<()> {
+            write!(self.inner, "{:08x}", self.offset)?;
+            for i in 0..LINE_LEN {
+                if i % 8 == 0 {
+                    write!(self.inner, " ")?;
+                }
+                match self.line.get(i) {
+                    Some(byte) => write!(self.inner, " {byte:02x}")?,
+                    None => write!(self.inner, "   ")?,
+                }
+            }
+            write!(self.inner, "  |")?;
+            for &byte in &self.line {
+                let c = if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                };
+                write!(self.inner, "{c}")?;
+            }
+            writeln!(self.inner, "|")?;
+            self.offset += self.line.len();
+            self.line.clear();
+            Ok(())
+        }
+    }
+
+    impl<W: Write> Write for HexDump<W> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            for &byte in buf {
+                self.line.push(byte);
+                if self.line.len() == LINE_LEN {
+                    self.write_line()?;
+                }
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    impl<W: Write> Drop for HexDump<W> {
+        fn drop(&mut self) {
+            if !self.line.is_empty() {
+                let _ = self.write_line();
+            }
+            let _ = writeln!(self.inner, "{:08x}", self.offset);
+            let _ = self.inner.flush();
+        }
+    }
+}
+
+pub use tail::Tail;
+pub mod tail {
+    use std::collections::VecDeque;
+    use std::io::{self, Write};
+
+    /// Keeps only the last `len` bytes written to it, in a ring buffer, for
+    /// programs where only the end of a huge output matters.
+    ///
+    /// What's kept is written to `inner` when the `Tail` is dropped.
+    pub struct Tail<W: Write> {
+        inner: W,
+        kept: VecDeque<u8>,
+        len: usize,
+        /// Bytes written in total, kept or not.
+        pub total: u64,
+    }
+
+    impl<W: Write> Tail<W> {
+        pub fn new(inner: W, len: usize) -> Self {
+            Self {
+                inner,
+                kept: VecDeque::with_capacity(len),
+                len,
+                total: 0,
+            }
+        }
+
+        /// The bytes kept so far, oldest first.
+        pub fn tail(&self) -> Vec<u8> {
+            self.kept.iter().copied().collect()
+        }
+    }
+
+    impl<W: Write> Write for Tail<W> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.total += buf.len() as u64;
+            let last = &buf[buf.len().saturating_sub(self.len)..];
+            let overflow = (self.kept.len() + last.len()).saturating_sub(self.len);
+            self.kept.drain(..overflow);
+            self.kept.extend(last);
+            Ok(buf.len())
+        }
+
+        /// Only flushes `inner`: nothing is written before the drop.
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    impl<W: Write> Drop for Tail<W> {
+        fn drop(&mut self) {
+            let (front, back) = self.kept.as_slices();
+            let _ = self.inner.write_all(front);
+            let _ = self.inner.write_all(back);
+            let _ = self.inner.flush();
+        }
+    }
+}
+
+pub use strip_control::StripControl;
+pub mod strip_control {
+    use std::io::{self, Write};
+
+    /// Drops a chosen set of control characters, like `\x07` (bell), from
+    /// everything written to it and passes the rest on.
+    pub struct StripControl<W: Write> {
+        inner: W,
+        /// Bit `n` set means byte `n` is dropped.
+        strip: u128,
+        buffer: Vec<u8>,
+    }
+
+    impl<W: Write> StripControl<W> {
+        /// Fails if one of `codes` isn't an ASCII control character.
+        pub fn new(inner: W, codes: &[u8]) -> Result<Self, &'static str> {
+            let mut strip = 0;
+            for &code in codes {
+                if !code.is_ascii_control() {
+                    return Err("only ASCII control characters can be stripped");
+                }
+                strip |= 1 << code;
+            }
+            Ok(Self {
+                inner,
+                strip,
+                buffer: Vec::new(),
+            })
+        }
+    }
+
+    impl<W: Write> Write for StripControl<W> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let strip = self.strip;
+            self.buffer.clear();
+            self.buffer.extend(
+                buf.iter()
+                    .copied()
+                    .filter(|&byte| byte >= 128 || strip & (1 << byte) == 0),
+            );
+            self.inner.write_all(&self.buffer)?;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
+}
+
+pub use stream::{spawn_streaming, ChannelReader, ChannelWriter};
+/// Runs a program on its own thread with input and output going through
+/// channels, so an async task can feed and drain it without blocking.
+///
+/// Only `std::sync::mpsc`: a full output channel blocks the interpreter thread
+/// until the receiver catches up, and so does waiting for input. Async code
+/// can read the receiver from `spawn_blocking` or a runtime's own bridge.
+pub mod stream {
+    use super::{BfEngine, ExitStatus, RunConfig};
+    use std::io::{self, Read, Write};
+    use std::sync::mpsc::{self, Receiver, SyncSender};
+    use std::thread::JoinHandle;
+
+    /// Sends everything written to it down a bounded channel, one message per
+    /// `write`. Wrap it in a `BufWriter` to send fewer, larger messages.
+    pub struct ChannelWriter(pub SyncSender<Vec<u8>>);
+
+    impl Write for ChannelWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.send(buf.to_vec()).map_err(|_| {
+                io::Error::new(io::ErrorKind::BrokenPipe, "output receiver dropped")
+            })?;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Reads from the chunks sent down a channel, blocking until the next one
+    /// arrives. Input ends once every sender has been dropped.
+    pub struct ChannelReader {
+        receiver: Receiver<Vec<u8>>,
+        chunk: Vec<u8>,
+        pos: usize,
+    }
+
+    impl ChannelReader {
+        pub fn new(receiver: Receiver<Vec<u8>>) -> Self {
+            Self {
+                receiver,
+                chunk: Vec::new(),
+                pos: 0,
+            }
+        }
+    }
+
+    impl Read for ChannelReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            while self.pos == self.chunk.len() {
+                match self.receiver.recv() {
+                    Ok(chunk) => {
+                        self.chunk = chunk;
+                        self.pos = 0;
+                    }
+                    Err(_) => return Ok(0),
+                }
+            }
+            let len = buf.len().min(self.chunk.len() - self.pos);
+            buf[..len].copy_from_slice(&self.chunk[self.pos..self.pos + len]);
+            self.pos += len;
+            Ok(len)
+        }
+    }
+
+    /// Starts running `opcodes` on a new thread, reading from `input` and
+    /// returning a receiver for its output, which holds at most `capacity`
+    /// unread writes. The handle gives the exit status once the program stops.
+    pub fn spawn_streaming<E: BfEngine>(
+        opcodes: Vec<E::OPCODE>,
+        input: Receiver<Vec<u8>>,
+        config: RunConfig,
+        capacity: usize,
+    ) -> (Receiver<Vec<u8>>, JoinHandle<ExitStatus>)
+    where
+        E::OPCODE: Send + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        let handle = std::thread::spawn(move || {
+            let mut input = ChannelReader::new(input);
+            let mut output = ChannelWriter(sender);
+            E::execute_fresh(&opcodes, &mut input, &mut output, &config).0
+        });
+        (receiver, handle)
+    }
+}
+
+pub use raw_engine::RawEngine;
+/// Runs the program one source character at a time with no optimization, as a
+/// reference for checking the other engines.
+pub mod raw_engine {
+    use super::{
+        read_byte, shift_pointer, BasicOpcode, BfEngine, Cell, ExitStatus, LoopOpcode, Observer,
+        Read, RunConfig, Write, DATA_LEN,
+    };
+
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub enum Opcode {
+        Add,
+        Sub,
+        /// Open
+        BranchZero(u32),
+        /// Close
+        BranchNotZero(u32),
+        Right,
+        Left,
+        Dot,
+        Comma,
+        End,
+        Store,
+        Restore,
+        ShiftBitsRight,
+        ShiftBitsLeft,
+    }
+
+    impl LoopOpcode for Opcode {
+        fn opens_loop(&self) -> bool {
+            matches!(self, Opcode::BranchZero(_))
+        }
+        fn closes_loop(&self) -> bool {
+            matches!(self, Opcode::BranchNotZero(_))
+        }
+    }
+
+    pub struct RawEngine {}
+
+    impl RawEngine {
+        /// `execute_observed` on a tape of any [`Cell`] type. Observers see
+        /// cells as bytes.
+        pub fn execute_cells<C: Cell>(
+            opcodes: &[Opcode],
+            data: &mut [C; DATA_LEN],
+            input: &mut impl Read,
+            output: &mut impl Write,
+            config: &RunConfig,
+            observer: &mut impl Observer,
+        ) -> ExitStatus {
+            let mut pc: usize = 0;
+            let mut dp: usize = 0;
+            // The Extended Type I storage register.
+            let mut register = C::from_byte(0);
+            while let Some(opcode) = opcodes.get(pc) {
+                observer.opcode(pc);
+                let byte = data[dp].to_byte();
+                match *opcode {
+                    Opcode::Add | Opcode::Sub => {
+                        let (value, wrapped) = if *opcode == Opcode::Add {
+                            byte.overflowing_add(1)
+                        } else {
+                            byte.overflowing_sub(1)
+                        };
+                        data[dp] = C::from_byte(value);
+                        observer.write(dp, value);
+                        if wrapped {
+                            observer.wrapped(dp);
+                        }
+                    }
+                    Opcode::BranchZero(i) => {
+                        if byte == 0 {
+                            pc = i as usize;
+                        }
+                    }
+                    Opcode::BranchNotZero(i) => {
+                        if byte != 0 {
+                            pc = i as usize;
+                        }
+                    }
+                    Opcode::Right | Opcode::Left => {
+                        let shift = if *opcode == Opcode::Right { 1 } else { -1 };
+                        match shift_pointer(dp, shift, config) {
+                            Ok(new_dp) => dp = new_dp,
+                            Err(status) => return status,
+                        }
+                        observer.moved(dp);
+                    }
+                    Opcode::Dot => {
+                        let _ = output.write(&[byte]);
+                        observer.output(byte);
+                    }
+                    Opcode::Comma => {
+                        let _ = output.flush();
+                        let Some(c) = read_byte(input) else {
+                            return ExitStatus::Halted;
+                        };
+                        data[dp] = C::from_byte(c);
+                        observer.write(dp, c);
+                    }
+                    Opcode::End => return ExitStatus::Halted,
+                    Opcode::Store => register = data[dp],
+                    Opcode::Restore | Opcode::ShiftBitsRight | Opcode::ShiftBitsLeft => {
+                        data[dp] = match *opcode {
+                            Opcode::Restore => register,
+                            Opcode::ShiftBitsRight => data[dp].shift_right(),
+                            _ => C::from_byte(byte << 1),
+                        };
+                        observer.write(dp, data[dp].to_byte());
+                    }
+                }
+                pc += 1;
+                if observer.pause(C::bytes(data), dp) {
+                    return observer.stop_status();
+                }
+            }
+            ExitStatus::Halted
+        }
+    }
+
+    impl BfEngine for RawEngine {
+        const NAME: &'static str = "Raw";
+        const DESCRIPTION: &'static str = "reference engine, one opcode per command";
+        const OPTIMIZATIONS: &'static [&'static str] = &[];
+
+        type OPCODE = Opcode;
+
+        fn generate(
+            iter: impl Iterator<Item = BasicOpcode>,
+        ) -> Result<Vec<Self::OPCODE>, &'static str> {
+            let mut buffer = Vec::new();
+            let mut open_stack: Vec<usize> = Vec::new();
+            for opcode in iter {
+                let next = match opcode {
+                    BasicOpcode::Add => Opcode::Add,
+                    BasicOpcode::Sub => Opcode::Sub,
+                    BasicOpcode::Right => Opcode::Right,
+                    BasicOpcode::Left => Opcode::Left,
+                    BasicOpcode::Dot => Opcode::Dot,
+                    BasicOpcode::Comma => Opcode::Comma,
+                    BasicOpcode::End => Opcode::End,
+                    BasicOpcode::Store => Opcode::Store,
+                    BasicOpcode::Restore => Opcode::Restore,
+                    BasicOpcode::ShiftBitsRight => Opcode::ShiftBitsRight,
+                    BasicOpcode::ShiftBitsLeft => Opcode::ShiftBitsLeft,
+                    BasicOpcode::Open => {
+                        open_stack.push(buffer.len());
+                        Opcode::BranchZero(0)
+                    }
+                    BasicOpcode::Close => {
+                        let other = open_stack.pop().ok_or("unbalanced brackets: extra ]")?;
+                        let this = buffer.len();
+                        buffer[other] =
+                            Opcode::BranchZero(this.try_into().map_err(|_| "program too large")?);
+                        Opcode::BranchNotZero(other as u32)
+                    }
+                };
+                buffer.push(next);
+            }
+            if !open_stack.is_empty() {
+                Err("unbalanced brackets: extra [")
+            } else {
+                Ok(buffer)
+            }
+        }
+
+        fn execute(
+            opcodes: &[Self::OPCODE],
+            data: &mut [u8; DATA_LEN],
+            input: &mut impl Read,
+            output: &mut impl Write,
+            config: &RunConfig,
+        ) -> ExitStatus {
+            Self::execute_observed(opcodes, data, input, output, config, &mut ())
+        }
+
+        fn execute_observed(
+            opcodes: &[Self::OPCODE],
+            data: &mut [u8; DATA_LEN],
+            input: &mut impl Read,
+            output: &mut impl Write,
+            config: &RunConfig,
+            observer: &mut impl Observer,
+        ) -> ExitStatus {
+            Self::execute_cells(opcodes, data, input, output, config, observer)
+        }
+    }
+}
+
+pub use merge_token_engine_extra::MergeTokenEngineExtra;
+pub mod merge_token_engine_extra {
+    use super::{
+        read_byte, shift_pointer, BasicOpcode, BfEngine, ExitStatus, LoopOpcode, Observer, Read,
+        RunConfig, Write, DATA_LEN, EXTENDED_UNSUPPORTED,
+    };
+
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub enum Opcode {
+        Add(u8),
+        /// Open
+        BranchZero(u32),
+        /// Close
+        BranchNotZero(u32),
+        Right(i16),
+        Dot,
+        Comma,
+
+        Clear,
+        AddTo(i16),
+        Seek(i16),
+    }
+
+    impl LoopOpcode for Opcode {
+        fn opens_loop(&self) -> bool {
+            matches!(self, Opcode::BranchZero(_))
+        }
+        fn closes_loop(&self) -> bool {
+            matches!(self, Opcode::BranchNotZero(_))
+        }
+    }
+
+    /// With `strict_tape`, checks only the net move of each merged run of `<>`.
+    pub struct MergeTokenEngineExtra {}
+    impl BfEngine for MergeTokenEngineExtra {
+        const NAME: &'static str = "Merge tokens extra";
+        const DESCRIPTION: &'static str =
+            "token merging plus the most common loops as single opcodes";
+        const OPTIMIZATIONS: &'static [&'static str] = &[
+            "merge +-",
+            "merge <>",
+            "clear loop",
+            "add-to loop",
+            "seek loop",
+        ];
+
+        type OPCODE = Opcode;
+
+        fn generate(
+            iter: impl Iterator<Item = BasicOpcode>,
+        ) -> Result<Vec<Self::OPCODE>, &'static str> {
+            let mut buffer = Vec::new();
+            use Opcode::*;
+            let mut open_stack: Vec<usize> = Vec::new();
+            for opcode in iter {
+                match opcode {
+                    BasicOpcode::Add => {
+                        if let Some(Add(p)) = buffer.last_mut() {
+                            *p = p.wrapping_add(1);
+                        } else {
+                            buffer.push(Add(1))
+                        }
+                    }
+                    BasicOpcode::Sub => {
+                        if let Some(Add(p)) = buffer.last_mut() {
+                            *p = p.wrapping_sub(1)
+                        } else {
+                            buffer.push(Add(-1_i32 as _))
+                        }
+                    }
+                    // A full `Right` starts another one rather than wrapping.
+                    BasicOpcode::Right => match buffer.last_mut() {
+                        Some(Right(p)) if *p < i16::MAX => *p += 1,
+                        _ => buffer.push(Right(1)),
+                    },
+                    BasicOpcode::Left => match buffer.last_mut() {
+                        Some(Right(p)) if *p > i16::MIN => *p -= 1,
+                        _ => buffer.push(Right(-1 as _)),
+                    },
+                    BasicOpcode::Open => {
+                        open_stack.push(buffer.len());
+                        buffer.push(BranchZero(0));
+                    }
+                    BasicOpcode::Close => {
+                        let other = open_stack.pop().ok_or("unbalanced brackets: extra ]")?;
+                        let this = buffer.len();
+                        buffer[other] =
+                            BranchZero(this.try_into().map_err(|_| "program too large")?);
+
+                        match buffer[..] {
+                            [.., BranchZero(_), Add(255)] => {
+                                buffer.truncate(buffer.len() - 2);
+                                buffer.push(Clear);
+                            }
+                            [.., BranchZero(_), Add(255), Right(x), Add(1), Right(y)]
+                                if x.checked_neg() == Some(y) =>
+                            {
+                                buffer.truncate(buffer.len() - 5);
+                                buffer.push(AddTo(x));
+                            }
+                            [.., BranchZero(_), Right(x)] => {
+                                buffer.truncate(buffer.len() - 2);
+                                buffer.push(Seek(x));
+                            }
+                            _ => {
+                                buffer.push(BranchNotZero(
+                                    other.try_into().map_err(|_| "program too large")?,
+                                ));
+                            }
+                        }
+                    }
+                    BasicOpcode::Dot => buffer.push(Dot),
+                    BasicOpcode::Comma => buffer.push(Comma),
+                    BasicOpcode::End
+                    | BasicOpcode::Store
+                    | BasicOpcode::Restore
+                    | BasicOpcode::ShiftBitsRight
+                    | BasicOpcode::ShiftBitsLeft => return Err(EXTENDED_UNSUPPORTED),
+                }
+                match buffer[..] {
+                    [.., Add(0)] | [.., Right(0)] | [.., AddTo(_), Clear] => {
+                        let _ = buffer.pop();
+                    }
+                    _ => (),
+                }
+            }
+
+            if !open_stack.is_empty() {
+                Err("unbalanced brackets: extra [")
+            } else {
+                Ok(buffer)
+            }
+        }
+
+        fn execute(
+            opcodes: &[Self::OPCODE],
+            data: &mut [u8; DATA_LEN],
+            input: &mut impl Read,
+            output: &mut impl Write,
+            config: &RunConfig,
+        ) -> ExitStatus {
+            Self::execute_observed(opcodes, data, input, output, config, &mut ())
+        }
+
+        fn execute_observed(
+            opcodes: &[Self::OPCODE],
+            data: &mut [u8; DATA_LEN],
+            input: &mut impl Read,
+            output: &mut impl Write,
+            config: &RunConfig,
+            observer: &mut impl Observer,
+        ) -> ExitStatus {
+            let mut pc: usize = 0;
+            let mut dp: usize = 0;
+            while let Some(opcode) = opcodes.get(pc) {
+                observer.opcode(pc);
+                match opcode {
+                    Opcode::Add(i) => {
+                        let (value, carry) = data[dp].overflowing_add(*i);
+                        data[dp] = value;
+                        observer.write(dp, value);
+                        // `i` is really signed: adding 255 is subtracting 1,
+                        // which wraps exactly when it doesn't carry.
+                        if carry == (*i < 128) {
+                            observer.wrapped(dp);
+                        }
+                    }
+                    Opcode::BranchZero(i) => {
+                        if data[dp] == 0 {
+                            pc = *i as _;
+                        }
+                    }
+                    Opcode::BranchNotZero(i) => {
+                        if data[dp] != 0 {
+                            pc = *i as _;
+                        }
+                    }
+                    Opcode::Right(i) => {
+                        match shift_pointer(dp, *i as _, config) {
+                            Ok(new_dp) => dp = new_dp,
+                            Err(status) => return status,
+                        }
+                        observer.moved(dp);
+                    }
+                    Opcode::Dot => {
+                        let _ = output.write(&[data[dp]]);
+                        observer.output(data[dp]);
+                    }
+                    Opcode::Comma => {
+                        let _ = output.flush();
+                        let Some(c) = read_byte(input) else {
+                            return ExitStatus::Halted;
+                        };
+                        data[dp] = c;
+                        observer.write(dp, c);
+                    }
+                    Opcode::Clear => {
+                        data[dp] = 0;
+                        observer.write(dp, 0);
+                    }
+                    // Like the loop, doesn't move at all on a zero, which matters
+                    // with `strict_tape`.
+                    Opcode::AddTo(i) if data[dp] != 0 => {
+                        let to = match shift_pointer(dp, *i as _, config) {
+                            Ok(to) => to,
+                            Err(status) => return status,
+                        };
+                        let (value, wrapped) = data[to].overflowing_add(data[dp]);
+                        data[to] = value;
+                        data[dp] = 0;
+                        observer.write(to, value);
+                        observer.write(dp, 0);
+                        if wrapped {
+                            observer.wrapped(to);
+                        }
+                    }
+                    Opcode::AddTo(_) => (),
+                    Opcode::Seek(i) => {
+                        for moved in 0.. {
+                            if data[dp] == 0 {
+                                break;
+                            }
+                            if moved == config.max_seek {
+                                return ExitStatus::SeekLimit;
+                            }
+                            match shift_pointer(dp, *i as _, config) {
+                                Ok(new_dp) => dp = new_dp,
+                                Err(status) => return status,
+                            }
+                            observer.moved(dp);
+                        }
+                    }
+                }
+                pc += 1;
+                if observer.pause(data, dp) {
+                    return observer.stop_status();
+                }
+            }
+            ExitStatus::Halted
+        }
+    }
+}
+
+pub use merge_token_engine::MergeTokenEngine;
+pub mod merge_token_engine {
+    use super::{
+        read_byte, shift_pointer, BasicOpcode, BfEngine, ExitStatus, LoopOpcode, Observer, Read,
+        RunConfig, Write, DATA_LEN, EXTENDED_UNSUPPORTED,
+    };
+
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub enum Opcode {
+        Add(u8),
+        /// Open
+        BranchZero(u32),
+        /// Close
+        BranchNotZero(u32),
+        Right(i16),
+        Dot,
+        Comma,
+    }
+
+    impl LoopOpcode for Opcode {
+        fn opens_loop(&self) -> bool {
+            matches!(self, Opcode::BranchZero(_))
+        }
+        fn closes_loop(&self) -> bool {
+            matches!(self, Opcode::BranchNotZero(_))
+        }
+    }
+
+    /// With `strict_tape`, checks only the net move of each merged run of `<>`.
+    pub struct MergeTokenEngine {}
+    impl BfEngine for MergeTokenEngine {
+        const NAME: &'static str = "Merge tokens basic";
+        const DESCRIPTION: &'static str = "token merging only";
+        const OPTIMIZATIONS: &'static [&'static str] = &["merge +-", "merge <>"];
+
+        type OPCODE = Opcode;
+
+        fn generate(
+            iter: impl Iterator<Item = BasicOpcode>,
+        ) -> Result<Vec<Self::OPCODE>, &'static str> {
+            let mut buffer = Vec::new();
+            use Opcode::*;
+            let mut open_stack: Vec<usize> = Vec::new();
+            for opcode in iter {
+                match opcode {
+                    BasicOpcode::Add => {
+                        if let Some(Add(p)) = buffer.last_mut() {
+                            *p = p.wrapping_add(1);
+                        } else {
+                            buffer.push(Add(1))
+                        }
+                    }
+                    BasicOpcode::Sub => {
+                        if let Some(Add(p)) = buffer.last_mut() {
+                            *p = p.wrapping_sub(1)
+                        } else {
+                            buffer.push(Add(-1_i32 as _))
+                        }
+                    }
+                    // A full `Right` starts another one rather than wrapping.
+                    BasicOpcode::Right => match buffer.last_mut() {
+                        Some(Right(p)) if *p < i16::MAX => *p += 1,
+                        _ => buffer.push(Right(1)),
+                    },
+                    BasicOpcode::Left => match buffer.last_mut() {
+                        Some(Right(p)) if *p > i16::MIN => *p -= 1,
+                        _ => buffer.push(Right(-1 as _)),
+                    },
+                    BasicOpcode::Open => {
+                        open_stack.push(buffer.len());
+                        buffer.push(BranchZero(0));
+                    }
+                    BasicOpcode::Close => {
+                        let other = open_stack.pop().ok_or("unbalanced brackets: extra ]")?;
+                        let this = buffer.len();
+                        buffer[other] =
+                            BranchZero(this.try_into().map_err(|_| "program too large")?);
+
+                        buffer.push(BranchNotZero(
+                            other.try_into().map_err(|_| "program too large")?,
+                        ));
+                    }
+                    BasicOpcode::Dot => buffer.push(Dot),
+                    BasicOpcode::Comma => buffer.push(Comma),
+                    BasicOpcode::End
+                    | BasicOpcode::Store
+                    | BasicOpcode::Restore
+                    | BasicOpcode::ShiftBitsRight
+                    | BasicOpcode::ShiftBitsLeft => return Err(EXTENDED_UNSUPPORTED),
+                }
+                match buffer[..] {
+                    [.., Add(0)] | [.., Right(0)] => {
+                        let _ = buffer.pop();
+                    }
+                    _ => (),
+                }
+            }
+
+            if !open_stack.is_empty() {
+                Err("unbalanced brackets: extra [")
+            } else {
+                Ok(buffer)
+            }
+        }
+
+        fn execute(
+            opcodes: &[Self::OPCODE],
+            data: &mut [u8; DATA_LEN],
+            input: &mut impl Read,
+            output: &mut impl Write,
+            config: &RunConfig,
+        ) -> ExitStatus {
+            Self::execute_observed(opcodes, data, input, output, config, &mut ())
+        }
+
+        fn execute_observed(
+            opcodes: &[Self::OPCODE],
+            data: &mut [u8; DATA_LEN],
+            input: &mut impl Read,
+            output: &mut impl Write,
+            config: &RunConfig,
+            observer: &mut impl Observer,
+        ) -> ExitStatus {
+            let mut pc: usize = 0;
+            let mut dp: usize = 0;
+            while let Some(opcode) = opcodes.get(pc) {
+                observer.opcode(pc);
+                match opcode {
+                    Opcode::Add(i) => {
+                        let (value, carry) = data[dp].overflowing_add(*i);
+                        data[dp] = value;
+                        observer.write(dp, value);
+                        // `i` is really signed: adding 255 is subtracting 1,
+                        // which wraps exactly when it doesn't carry.
+                        if carry == (*i < 128) {
+                            observer.wrapped(dp);
+                        }
+                    }
+                    Opcode::BranchZero(i) => {
+                        if data[dp] == 0 {
+                            pc = *i as _;
+                        }
+                    }
+                    Opcode::BranchNotZero(i) => {
+                        if data[dp] != 0 {
+                            pc = *i as _;
+                        }
+                    }
+                    Opcode::Right(i) => {
+                        match shift_pointer(dp, *i as _, config) {
+                            Ok(new_dp) => dp = new_dp,
+                            Err(status) => return status,
+                        }
+                        observer.moved(dp);
+                    }
+                    Opcode::Dot => {
+                        let _ = output.write(&[data[dp]]);
+                        observer.output(data[dp]);
+                    }
+                    Opcode::Comma => {
+                        let _ = output.flush();
+                        let Some(c) = read_byte(input) else {
+                            return ExitStatus::Halted;
+                        };
+                        data[dp] = c;
+                        observer.write(dp, c);
+                    }
+                }
+                pc += 1;
+                if observer.pause(data, dp) {
+                    return observer.stop_status();
+                }
+            }
+            ExitStatus::Halted
+        }
+    }
+}
+pub use shift_add_engine::{
+    execute_traced, fold_constant_loops, group_blocks, is_obviously_terminating, print_profile,
+    reads_input, remove_dead_code, remove_unreachable, unreachable_opcodes, validate,
+    writes_output, BasicOpEstimate, CheckedShiftAddEngine, Coverage, Interpreter, OpcodeStats,
+    OptLevel, OptimizationLog, Pass, ShiftAddEngine, SourceSpan, StateSnapshot, TraceEvent,
+};
+pub mod shift_add_engine {
+    use super::{
+        new_tape, read_byte, shift_pointer, to_basic_opcode, BasicOpcode, BfEngine, ExitStatus,
+        Indent, LoopOpcode, Observer, Profile, Read, RunConfig, Write, DATA_LEN,
+        EXTENDED_UNSUPPORTED,
+    };
+    use std::collections::HashMap;
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub enum Opcode {
+        /// Open
+        BranchZero(u32),
+        /// Close
+        BranchNotZero(u32),
+        /// Add to the current cell, then move. Always in that order, so `>+`
+        /// stays `AddRight(0, 1), AddRight(1, 0)` while `+>` is `AddRight(1, 1)`.
+        AddRight(u8, i16), // inc, shift
+
+        SetRight(u8, i16), // set, shift
+        AddTo(i16),        // introduce set?
+        SubTo(i16),        // intrucude set?
+        /// `AddTo` then move onto the cell added to, for `[->+<]>`
+        MoveTo(i16),
+
+        Seek(i16),
+        //Add(u8),
+        //Right(i16),
+        Dot,
+        Comma,
+
+        /// Output a byte known at compile time `count` times
+        EmitConst(u8, u16), // byte, count
+
+        /// `x[y+t+x-]t[x+t-]`: add the current cell to `dst`, move `temp` back
+        /// into it and end up on `temp`
+        Copy(i16, i16), // dst, temp
+
+        /// Run the next `count` opcodes, all `AddRight`, `SetRight`, `AddTo`,
+        /// `SubTo` or `MoveTo`, in one go
+        Block(u16), // count
+
+        Exit,
+    }
+
+    impl LoopOpcode for Opcode {
+        fn opens_loop(&self) -> bool {
+            matches!(self, Opcode::BranchZero(_))
+        }
+        fn closes_loop(&self) -> bool {
+            matches!(self, Opcode::BranchNotZero(_))
+        }
+    }
+
+    impl Opcode {
+        /// Every variant, in the order `index` numbers them.
+        pub const NAMES: [&'static str; 14] = [
+            "BranchZero",
+            "BranchNotZero",
+            "AddRight",
+            "SetRight",
+            "AddTo",
+            "SubTo",
+            "MoveTo",
+            "Seek",
+            "Dot",
+            "Comma",
+            "EmitConst",
+            "Copy",
+            "Block",
+            "Exit",
+        ];
+
+        pub fn index(&self) -> usize {
+            use Opcode::*;
+            match self {
+                BranchZero(_) => 0,
+                BranchNotZero(_) => 1,
+                AddRight(..) => 2,
+                SetRight(..) => 3,
+                AddTo(_) => 4,
+                SubTo(_) => 5,
+                MoveTo(_) => 6,
+                Seek(_) => 7,
+                Dot => 8,
+                Comma => 9,
+                EmitConst(..) => 10,
+                Copy(..) => 11,
+                Block(_) => 12,
+                Exit => 13,
+            }
+        }
+
+        pub fn name(&self) -> &'static str {
+            Self::NAMES[self.index()]
+        }
+    }
+
+    /// Checks what [`ShiftAddEngine::execute`] relies on to skip bounds checks:
+    /// the program ends in `Exit`, every branch points at its partner, and every
+    /// `Block` covers only straight-line opcodes that are there.
+    /// [`ShiftAddEngine::generate`] always passes; hand-built or rewritten
+    /// opcodes might not.
+    pub fn validate(opcodes: &[Opcode]) -> Result<(), String> {
+        use Opcode::*;
+        if opcodes.last() != Some(&Exit) {
+            return Err("program doesn't end in Exit".to_owned());
+        }
+        for (pc, opcode) in opcodes.iter().enumerate() {
+            let error = match *opcode {
+                BranchZero(close) => {
+                    let paired = opcodes.get(close as usize) == Some(&BranchNotZero(pc as u32));
+                    (close as usize <= pc || !paired)
+                        .then_some("BranchZero doesn't point at its BranchNotZero")
+                }
+                BranchNotZero(open) => {
+                    let paired = opcodes.get(open as usize) == Some(&BranchZero(pc as u32));
+                    (open as usize >= pc || !paired)
+                        .then_some("BranchNotZero doesn't point at its BranchZero")
+                }
+                Block(count) => match opcodes.get(pc + 1..=pc + count as usize) {
+                    None => Some("Block runs past the end of the program"),
+                    Some(inner) => (!inner.iter().all(|opcode| {
+                        matches!(
+                            opcode,
+                            AddRight(..) | SetRight(..) | AddTo(_) | SubTo(_) | MoveTo(_)
+                        )
+                    }))
+                    .then_some("Block holds an opcode that isn't straight-line"),
+                },
+                _ => None,
+            };
+            if let Some(error) = error {
+                return Err(format!("opcode {pc}: {error}"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Turns `x[y+t+x-]t[x+t-]`, a transfer of `x` into `y` and `t` followed by
+    /// a transfer of `t` back into `x`, into a single `Copy`. Returns whether it
+    /// did.
+    fn fuse_copy(buffer: &mut Vec<Opcode>) -> bool {
+        use Opcode::*;
+        let [.., BranchNotZero(open), AddRight(0, temp), AddTo(back)] = buffer[..] else {
+            return false;
+        };
+        if back != -temp {
+            return false;
+        }
+        let open = open as usize;
+
+        let mut offset: i32 = 0;
+        let mut deltas: Vec<(i32, u8)> = Vec::new();
+        for opcode in &buffer[open + 1..buffer.len() - 3] {
+            let AddRight(add, shift) = *opcode else {
+                return false;
+            };
+            match deltas.iter_mut().find(|(o, _)| *o == offset) {
+                Some((_, delta)) => *delta = delta.wrapping_add(add),
+                None => deltas.push((offset, add)),
+            }
+            offset += shift as i32;
+        }
+        deltas.retain(|&(_, delta)| delta != 0);
+        deltas.sort();
+        if offset != 0 || deltas.len() != 3 {
+            return false;
+        }
+        let delta_at = |o: i32| deltas.iter().find(|&&(x, _)| x == o).map(|&(_, d)| d);
+        if delta_at(0) != Some(255) || delta_at(temp as i32) != Some(1) {
+            return false;
+        }
+        let Some(&(dst, 1)) = deltas.iter().find(|&&(o, _)| o != 0 && o != temp as i32) else {
+            return false;
+        };
+        let Ok(dst) = dst.try_into() else {
+            return false;
+        };
+        buffer.truncate(open);
+        buffer.push(Copy(dst, temp));
+        true
+    }
+
+    /// Drops clears whose cell is set again before anything reads it, like the
+    /// first `[-]` in `[-]>+<[-]+`. Clears that also move become plain moves.
+    /// Returns how many clears were dropped.
+    fn remove_dead_clears(opcodes: &mut Vec<Opcode>, spans: Option<&mut Vec<SourceSpan>>) -> usize {
+        use Opcode::*;
+        let cell = |offset: isize| offset.rem_euclid(DATA_LEN as isize);
+        let overwritten = |rest: &[Opcode], mut offset: isize| {
+            for opcode in rest {
+                match *opcode {
+                    AddRight(a, s) => {
+                        if a != 0 && cell(offset) == 0 {
+                            return false;
+                        }
+                        offset += s as isize;
+                    }
+                    SetRight(_, s) => {
+                        if cell(offset) == 0 {
+                            return true;
+                        }
+                        offset += s as isize;
+                    }
+                    AddTo(o) | SubTo(o) | MoveTo(o) => {
+                        if cell(offset) == 0 || cell(offset + o as isize) == 0 {
+                            return false;
+                        }
+                        if let MoveTo(_) = opcode {
+                            offset += o as isize;
+                        }
+                    }
+                    Dot if cell(offset) == 0 => return false,
+                    Dot | EmitConst(..) | Block(_) => (),
+                    _ => return false,
+                }
+            }
+            false
+        };
+        let mut removed = vec![false; opcodes.len()];
+        let mut dropped = 0;
+        for pc in 0..opcodes.len() {
+            if let SetRight(0, s) = opcodes[pc] {
+                if overwritten(&opcodes[pc + 1..], s as isize) {
+                    if s == 0 {
+                        removed[pc] = true;
+                    } else {
+                        opcodes[pc] = AddRight(0, s);
+                    }
+                    dropped += 1;
+                }
+            }
+        }
+        remove_marked(opcodes, &removed);
+        if let Some(spans) = spans {
+            remove_marked_spans(spans, &removed);
+        }
+        dropped
+    }
+
+    /// Whether the cell `opcodes` ends on was cleared and not written since,
+    /// looking back only over straight-line adds, sets and outputs.
+    fn cleared(opcodes: &[Opcode]) -> bool {
+        use Opcode::*;
+        let mut offset = 0isize;
+        for opcode in opcodes.iter().rev() {
+            match *opcode {
+                AddRight(a, s) | SetRight(a, s) => {
+                    offset -= s as isize;
+                    if offset.rem_euclid(DATA_LEN as isize) == 0 {
+                        match opcode {
+                            SetRight(..) => return a == 0,
+                            _ if a != 0 => return false,
+                            _ => (),
+                        }
+                    }
+                }
+                Dot | EmitConst(..) => (),
+                _ => return false,
+            }
+        }
+        false
+    }
+
+    /// Replaces `AddTo(x)` followed by `AddRight(0, x)` with `MoveTo(x)`.
+    /// Returns how many were replaced.
+    fn fuse_move_to(opcodes: &mut Vec<Opcode>, mut spans: Option<&mut Vec<SourceSpan>>) -> usize {
+        use Opcode::*;
+        let mut removed = vec![false; opcodes.len()];
+        let mut fused = 0;
+        for pc in 1..opcodes.len() {
+            if let [AddTo(to), AddRight(0, shift)] = opcodes[pc - 1..=pc] {
+                if to == shift {
+                    opcodes[pc - 1] = MoveTo(to);
+                    removed[pc] = true;
+                    fused += 1;
+                    if let Some(spans) = spans.as_deref_mut() {
+                        spans[pc - 1].end = spans[pc].end;
+                    }
+                }
+            }
+        }
+        remove_marked(opcodes, &removed);
+        if let Some(spans) = spans {
+            remove_marked_spans(spans, &removed);
+        }
+        fused
+    }
+
+    /// Drops the opcodes marked in `removed` and fixes the branch targets of the
+    /// rest, none of which may point at a removed opcode.
+    fn remove_marked(opcodes: &mut Vec<Opcode>, removed: &[bool]) {
+        use Opcode::*;
+        if !removed.contains(&true) {
+            return;
+        }
+        // Where each opcode ends up, for fixing the branches afterwards.
+        let mut moved_to = Vec::with_capacity(opcodes.len());
+        let mut kept = 0;
+        for &removed in removed {
+            moved_to.push(kept);
+            kept += !removed as usize;
+        }
+        let mut i = 0;
+        opcodes.retain(|_| {
+            i += 1;
+            !removed[i - 1]
+        });
+        for opcode in opcodes.iter_mut() {
+            if let BranchZero(target) | BranchNotZero(target) = opcode {
+                *target = moved_to[*target as usize].try_into().unwrap();
+            }
+        }
+    }
+
+    /// Drops the spans of the opcodes `remove_marked` dropped.
+    fn remove_marked_spans(spans: &mut Vec<SourceSpan>, removed: &[bool]) {
+        let mut i = 0;
+        spans.retain(|_| {
+            i += 1;
+            !removed[i - 1]
+        });
+    }
+
+    /// Puts a `Block` in front of every run of two or more branch-free opcodes so
+    /// they are dispatched from a tight inner loop. Not done by `generate`: the
+    /// inner loop still matches on every opcode, and a nested-loop benchmark ran
+    /// about 25% slower than plain dispatch. Blocks already in `opcodes` are
+    /// kept as they are. Panics if `opcodes` doesn't pass [`validate`].
+    pub fn group_blocks(opcodes: &[Opcode]) -> Vec<Opcode> {
+        use Opcode::*;
+        if let Err(e) = validate(opcodes) {
+            panic!("group_blocks on a malformed program: {e}");
+        }
+        let straight_line = |opcode: &Opcode| {
+            matches!(
+                opcode,
+                AddRight(..) | SetRight(..) | AddTo(_) | SubTo(_) | MoveTo(_)
+            )
+        };
+        let mut grouped = Vec::with_capacity(opcodes.len());
+        // Where each old opcode ended up, for fixing the branches afterwards.
+        let mut moved_to = Vec::with_capacity(opcodes.len());
+        let mut pc = 0;
+        while pc < opcodes.len() {
+            let run = opcodes[pc..]
+                .iter()
+                .take(u16::MAX as usize)
+                .take_while(|opcode| straight_line(opcode))
+                .count();
+            if run >= 2 {
+                grouped.push(Block(run as u16));
+            } else if run == 0 {
+                let len = match opcodes[pc] {
+                    Block(count) => 1 + count as usize,
+                    _ => 1,
+                };
+                for opcode in &opcodes[pc..pc + len] {
+                    moved_to.push(grouped.len());
+                    grouped.push(*opcode);
+                }
+                pc += len;
+                continue;
+            }
+            for opcode in &opcodes[pc..pc + run] {
+                moved_to.push(grouped.len());
+                grouped.push(*opcode);
+            }
+            pc += run;
+        }
+        for opcode in &mut grouped {
+            if let BranchZero(target) | BranchNotZero(target) = opcode {
+                *target = moved_to[*target as usize].try_into().unwrap();
+            }
+        }
+        if let Err(e) = validate(&grouped) {
+            panic!("group_blocks produced a malformed program: {e}");
+        }
+        grouped
+    }
+
+    /// The source bytes `start..end` an opcode was compiled from, covering every
+    /// command folded into it.
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub struct SourceSpan {
+        pub start: usize,
+        pub end: usize,
+    }
+
+    /// How often each rewrite fired while generating, from
+    /// `ShiftAddEngine::generate_logged`.
+    #[derive(Clone, Debug, Default)]
+    pub struct OptimizationLog {
+        /// Tokens folded into the opcode before them.
+        pub merged: usize,
+        /// `[-]` loops turned into `SetRight`.
+        pub clears: usize,
+        pub add_tos: usize,
+        pub sub_tos: usize,
+        pub seeks: usize,
+        pub copies: usize,
+        /// `.` on a known cell value turned into `EmitConst`.
+        pub constant_outputs: usize,
+        /// Loops with a known counter replaced by the stores they do.
+        pub constant_loops: usize,
+        /// Opcodes with no effect dropped while merging.
+        pub redundant: usize,
+        /// Clears dropped by `remove_dead_clears`.
+        pub dead_clears: usize,
+        /// Loops dropped because their cell is already zero on entry, like `[-][body]`.
+        pub dead_loops: usize,
+        pub move_tos: usize,
+        /// Command index of the `]` of each loop that stayed a plain loop.
+        pub plain_loops: Vec<usize>,
+    }
+    impl std::fmt::Display for OptimizationLog {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let rules = [
+                ("merged", self.merged),
+                ("clear", self.clears),
+                ("add_to", self.add_tos),
+                ("sub_to", self.sub_tos),
+                ("seek", self.seeks),
+                ("copy", self.copies),
+                ("emit_const", self.constant_outputs),
+                ("const_loop", self.constant_loops),
+                ("redundant", self.redundant),
+                ("dead_clear", self.dead_clears),
+                ("dead_loop", self.dead_loops),
+                ("move_to", self.move_tos),
+                ("plain_loop", self.plain_loops.len()),
+            ];
+            for (rule, count) in rules {
+                writeln!(f, "{rule:>12} {count:>9}")?;
+            }
+            Ok(())
+        }
+    }
+
+    /// How much `ShiftAddEngine::generate_at` rewrites.
+    #[derive(Copy, Clone, Debug, Default, Eq, PartialEq, PartialOrd, Ord)]
+    pub enum OptLevel {
+        /// One `AddRight` or branch per command, so opcodes line up with the
+        /// source.
+        O0,
+        /// Runs of `+-` and `<>` merged, each add carrying the move after it.
+        O1,
+        /// Everything `generate` does: clears, transfers, seeks, copies and
+        /// constant output as well.
+        #[default]
+        O2,
+    }
+
+    /// Where a rewrite needing `needed` may start looking in a buffer of `len`
+    /// opcodes: anywhere, or below that level only at the empty end, where
+    /// nothing but a match's catch-all arm fits.
+    fn rewritable(len: usize, level: OptLevel, needed: OptLevel) -> usize {
+        if level >= needed {
+            0
+        } else {
+            len
+        }
+    }
+
+    /// An extra rewrite to run after the built-in ones, see
+    /// `ShiftAddEngine::generate_with_passes`.
+    pub type Pass = fn(&mut Vec<Opcode>);
+
+    /// With `strict_tape`, checks only the net move of each merged run of `<>`.
+    pub struct ShiftAddEngine {}
+    impl ShiftAddEngine {
+        /// `generate`, then each of `passes` in order. A pass that adds or
+        /// removes opcodes has to fix the branch targets itself; if the result
+        /// doesn't pass [`validate`], that's an error.
+        pub fn generate_with_passes(
+            iter: impl Iterator<Item = BasicOpcode>,
+            passes: &[Pass],
+        ) -> Result<Vec<Opcode>, &'static str> {
+            let mut opcodes = Self::generate(iter)?;
+            for pass in passes {
+                pass(&mut opcodes);
+            }
+            validate(&opcodes).map_err(|_| "a pass left the program malformed")?;
+            Ok(opcodes)
+        }
+
+        /// `generate`, also counting how often each rewrite fired.
+        pub fn generate_logged(
+            iter: impl Iterator<Item = BasicOpcode>,
+        ) -> Result<(Vec<Opcode>, OptimizationLog), &'static str> {
+            Self::generate_at(iter, OptLevel::O2)
+        }
+
+        /// `generate_logged`, rewriting only as much as `level` allows.
+        pub fn generate_at(
+            iter: impl Iterator<Item = BasicOpcode>,
+            level: OptLevel,
+        ) -> Result<(Vec<Opcode>, OptimizationLog), &'static str> {
+            Self::generate_with(iter.enumerate(), None, level)
+        }
+
+        /// `generate` from source bytes, also returning where in `src` each
+        /// opcode came from. Keeps a second copy of the opcodes while compiling.
+        pub fn generate_spanned(
+            src: &[u8],
+        ) -> Result<(Vec<Opcode>, Vec<SourceSpan>), &'static str> {
+            let (opcodes, _, spans) = Self::generate_annotated(src)?;
+            Ok((opcodes, spans))
+        }
+
+        /// `generate_spanned` and `generate_logged` at once.
+        pub fn generate_annotated(
+            src: &[u8],
+        ) -> Result<(Vec<Opcode>, OptimizationLog, Vec<SourceSpan>), &'static str> {
+            let iter = (src.iter().enumerate())
+                .filter_map(|(offset, &c)| Some((offset, to_basic_opcode(c)?)));
+            let mut spans = Vec::new();
+            let (opcodes, log) = Self::generate_with(iter, Some(&mut spans), OptLevel::O2)?;
+            Ok((opcodes, log, spans))
+        }
+
+        /// Takes each command with its position, which is what `plain_loops`
+        /// and the spans refer to.
+        fn generate_with(
+            iter: impl Iterator<Item = (usize, BasicOpcode)>,
+            mut spans: Option<&mut Vec<SourceSpan>>,
+            level: OptLevel,
+        ) -> Result<(Vec<Opcode>, OptimizationLog), &'static str> {
+            let mut log = OptimizationLog::default();
+            let mut owned_buffer = Vec::new();
+            let buffer = &mut owned_buffer;
+            use Opcode::*;
+            // Buffer index and command of each `[` not yet closed.
+            let mut open_stack: Vec<(usize, usize)> = Vec::new();
+            // The buffer as of the last command, to see what each command changed.
+            let mut shadow: Vec<Opcode> = Vec::new();
+            let mut end = 0;
+            for (command, opcode) in iter {
+                match opcode {
+                    BasicOpcode::Add => buffer.push(AddRight(1 as _, 0 as _)),
+                    BasicOpcode::Sub => buffer.push(AddRight(-1 as _, 0 as _)),
+                    BasicOpcode::Right => buffer.push(AddRight(0 as _, 1 as _)),
+                    BasicOpcode::Left => buffer.push(AddRight(0 as _, -1 as _)),
+                    BasicOpcode::Open => {
+                        open_stack.push((buffer.len(), command));
+                        buffer.push(BranchZero(0));
+                    }
+                    BasicOpcode::Close => {
+                        let (other, open) =
+                            open_stack.pop().ok_or("unbalanced brackets: extra ]")?;
+                        let this = buffer.len();
+                        buffer[other] =
+                            BranchZero(this.try_into().map_err(|_| "program too large")?);
+
+                        // A `BranchZero` in these patterns is always `other`: an inner
+                        // loop either still ends in its `BranchNotZero` or was fused
+                        // into opcodes without one, so `[[-]]` or `[->[-]+<]` can't
+                        // match across loop boundaries.
+                        match buffer[rewritable(this, level, OptLevel::O2)..] {
+                            // `[-][body]`: the cell is zero on entry, so the body and
+                            // any loops inside it never run.
+                            [..] if level >= OptLevel::O2 && cleared(&buffer[..other]) => {
+                                buffer.truncate(other);
+                                while log.plain_loops.last().is_some_and(|&close| close > open) {
+                                    let _ = log.plain_loops.pop();
+                                }
+                                log.dead_loops += 1;
+                            }
+                            // `[-]` and `[+]`, or any odd step, since those reach zero from
+                            // every value. Even steps can loop forever and stay loops.
+                            [.., BranchZero(_), AddRight(a, 0)] if a % 2 == 1 => {
+                                buffer.truncate(buffer.len() - 2);
+                                buffer.push(SetRight(0, 0));
+                                log.clears += 1;
+                            }
+                            // `checked_neg` keeps `i16::MIN` offsets as plain loops.
+                            [.., BranchZero(_), AddRight(255, x), AddRight(1, y)]
+                                if x.checked_neg() == Some(y) && x != 0 =>
+                            {
+                                buffer.truncate(buffer.len() - 3);
+                                buffer.push(AddTo(x));
+                                log.add_tos += 1;
+                            }
+                            [.., BranchZero(_), AddRight(255, x), AddRight(255, y)]
+                                if x.checked_neg() == Some(y) && x != 0 =>
+                            {
+                                buffer.truncate(buffer.len() - 3);
+                                buffer.push(SubTo(x));
+                                log.sub_tos += 1;
+                            }
+                            // The same transfers with the decrement last, `[>+<-]`
+                            [.., BranchZero(_), AddRight(0, x), AddRight(1, y), AddRight(255, 0)]
+                                if x.checked_neg() == Some(y) && x != 0 =>
+                            {
+                                buffer.truncate(buffer.len() - 4);
+                                buffer.push(AddTo(x));
+                                log.add_tos += 1;
+                            }
+                            [.., BranchZero(_), AddRight(0, x), AddRight(255, y), AddRight(255, 0)]
+                                if x.checked_neg() == Some(y) && x != 0 =>
+                            {
+                                buffer.truncate(buffer.len() - 4);
+                                buffer.push(SubTo(x));
+                                log.sub_tos += 1;
+                            }
+                            // Only a bare move: `[->]` decrements as it goes and
+                            // has to stay a loop.
+                            [.., BranchZero(_), AddRight(0, x)] if x != 0 => {
+                                buffer.truncate(buffer.len() - 2);
+                                buffer.push(Seek(x));
+                                log.seeks += 1;
+                            }
+                            _ => {
+                                buffer.push(BranchNotZero(
+                                    other.try_into().map_err(|_| "program too large")?,
+                                ));
+                                log.plain_loops.push(command);
+                            }
+                        }
+                        if level >= OptLevel::O2 && fuse_copy(buffer) {
+                            log.copies += 1;
+                            // The first of the two loops, which did end up fused.
+                            log.plain_loops.pop();
+                        }
+                    }
+                    BasicOpcode::Dot => {
+                        match buffer[rewritable(buffer.len(), level, OptLevel::O2)..] {
+                            [.., SetRight(byte, 0)] => {
+                                buffer.push(EmitConst(byte, 1));
+                                log.constant_outputs += 1;
+                            }
+                            [.., EmitConst(byte, count)] if count < u16::MAX => {
+                                *buffer.last_mut().unwrap() = EmitConst(byte, count + 1);
+                                log.constant_outputs += 1;
+                            }
+                            [.., EmitConst(byte, _)] => {
+                                buffer.push(EmitConst(byte, 1));
+                                log.constant_outputs += 1;
+                            }
+                            _ => buffer.push(Dot),
+                        }
+                    }
+                    // Never merged with what comes before or after: the merge arms below
+                    // only fold `AddRight` into an `AddRight` or `SetRight`, so `,-----` stays
+                    // a read followed by a subtract.
+                    BasicOpcode::Comma => buffer.push(Comma),
+                    BasicOpcode::End
+                    | BasicOpcode::Store
+                    | BasicOpcode::Restore
+                    | BasicOpcode::ShiftBitsRight
+                    | BasicOpcode::ShiftBitsLeft => return Err(EXTENDED_UNSUPPORTED),
+                }
+
+                let mut redundant_code_found = true;
+
+                // An add only folds into an opcode that hasn't moved yet, and a
+                // move into anything, which keeps every add before its move.
+                let start = rewritable(buffer.len(), level, OptLevel::O1);
+                match &mut buffer[start..] {
+                    [.., AddRight(curr_add, curr_shift @ 0) | SetRight(curr_add, curr_shift @ 0), AddRight(add, shift)] =>
+                    {
+                        *curr_add = curr_add.wrapping_add(*add);
+                        *curr_shift = *shift;
+                        let _ = buffer.pop();
+                        log.merged += 1;
+                    }
+                    // Left as two opcodes if the sum won't fit in an `i16`.
+                    [.., AddRight(_, curr_shift) | SetRight(_, curr_shift), AddRight(0, shift)]
+                        if curr_shift.checked_add(*shift).is_some() =>
+                    {
+                        *curr_shift += *shift;
+                        let _ = buffer.pop();
+                        log.merged += 1;
+                    }
+                    _ => (),
+                }
+
+                match buffer[rewritable(buffer.len(), level, OptLevel::O1)..] {
+                    // A clear of a cell that's already clear, like the last one
+                    // in `[-]>[-]<[-]`.
+                    [.., SetRight(0, 0)] if cleared(&buffer[..buffer.len() - 1]) => {
+                        let _ = buffer.pop();
+                    }
+                    [.., AddRight(_, 0) | SetRight(_, 0), a @ SetRight(0, 0)] => {
+                        buffer.truncate(buffer.len() - 2);
+                        buffer.push(a);
+                    }
+                    // A loop right after a clear never runs.
+                    [.., SetRight(0, 0), AddTo(_) | SubTo(_) | Seek(_)] => {
+                        let _ = buffer.pop();
+                    }
+                    // `+-`, `<>` and the like.
+                    [.., AddRight(0, 0)] => {
+                        let _ = buffer.pop();
+                    }
+                    _ => redundant_code_found = false,
+                }
+                if redundant_code_found {
+                    log.redundant += 1;
+                }
+
+                end = command + 1;
+                if let Some(spans) = spans.as_deref_mut() {
+                    // Commands only rewrite the end of the buffer, so whatever
+                    // differs from the shadow now covers this command as well.
+                    let mut kept = shadow.len().min(buffer.len());
+                    while kept > 0 && shadow[kept - 1] != buffer[kept - 1] {
+                        kept -= 1;
+                    }
+                    let start = spans.get(kept).map_or(command, |span| span.start);
+                    spans.truncate(kept);
+                    spans.resize(buffer.len(), SourceSpan { start, end });
+                    shadow.truncate(kept);
+                    shadow.extend_from_slice(&buffer[kept..]);
+                }
+            }
+            buffer.push(Exit);
+            if let Some(spans) = spans.as_deref_mut() {
+                spans.push(SourceSpan { start: end, end });
+            }
+            #[allow(unused_mut)]
+            let mut patterns: HashMap<&str, usize> = HashMap::new();
+
+            #[allow(unused_macros)]
+            macro_rules! pattern {
+                    ($i:ident, $pattern:pat $(if $guard:expr)? $(,)?) => {
+                        {
+                            let s = stringify!($pattern $(if $guard)?);
+                            match &buffer[..$i] {
+                                $pattern $(if $guard)? => *patterns.entry(s).or_default() += 1,
+                                _ => ()
+                            }
+                        }
+                    };
+                }
+            //for i in 0..buffer.len() {
+            //    //pattern!(i, &[.., AddTo(x), AddRight(0, y)] if x == y);
+            //    //pattern!(i, &[.., AddTo(_), AddRight(_, _)]);
+            //    //pattern!(i, &[.., AddTo(_), AddRight(0, _)]);
+            //    //pattern!(i, &[.., AddTo(_), AddRight(_, 0)]);
+            //    //pattern!(i, &[.., AddTo(_), AddRight(0, 0)]);
+            //    pattern!(i, &[.., Clear, AddRight(_, _)]);
+            //    pattern!(i, &[.., Clear, AddRight(0, _)]);
+            //    pattern!(i, &[.., Clear, AddRight(_, 0)]);
+            //    pattern!(i, &[.., Clear, AddRight(0, 0)]);
+            //    pattern!(i, &[.., Clear]);
+            //}
+
+            if !patterns.is_empty() {
+                dbg!(&patterns);
+            }
+
+            if !open_stack.is_empty() {
+                Err("unbalanced brackets: extra [")
+            } else {
+                if level >= OptLevel::O2 {
+                    log.dead_clears = remove_dead_clears(&mut owned_buffer, spans.as_deref_mut());
+                    log.move_tos = fuse_move_to(&mut owned_buffer, spans.as_deref_mut());
+                    let folded = fold_constants(&mut owned_buffer, KnownCells::unknown(), spans);
+                    log.constant_loops = folded.loops;
+                    log.constant_outputs += folded.outputs;
+                }
+                Ok((owned_buffer, log))
+            }
+        }
+    }
+
+    impl BfEngine for ShiftAddEngine {
+        const NAME: &'static str = "Shift add";
+        const DESCRIPTION: &'static str = "every cell update carries the move after it, fastest";
+        const OPTIMIZATIONS: &'static [&'static str] = &[
+            "merge +-",
+            "merge <>",
+            "add then move",
+            "clear loop",
+            "set then move",
+            "add-to loop",
+            "sub-to loop",
+            "move-to",
+            "copy loop",
+            "seek loop",
+            "constant output",
+            "constant loop folding",
+            "dead clear removal",
+        ];
+
+        type OPCODE = Opcode;
+
+        fn generate(
+            iter: impl Iterator<Item = BasicOpcode>,
+        ) -> Result<Vec<Self::OPCODE>, &'static str> {
+            Self::generate_logged(iter).map(|(opcodes, _)| opcodes)
+        }
+
+        #[inline(never)]
+        fn execute(
+            opcodes: &[Self::OPCODE],
+            data: &mut [u8; DATA_LEN],
+            input: &mut impl Read,
+            output: &mut impl Write,
+            config: &RunConfig,
+        ) -> ExitStatus {
+            Self::execute_observed(opcodes, data, input, output, config, &mut ())
+        }
+
+        #[inline(never)]
+        fn execute_observed(
+            opcodes: &[Self::OPCODE],
+            data: &mut [u8; DATA_LEN],
+            input: &mut impl Read,
+            output: &mut impl Write,
+            config: &RunConfig,
+            observer: &mut impl Observer,
+        ) -> ExitStatus {
+            if let Err(e) = validate(opcodes) {
+                panic!("malformed ShiftAddEngine program: {e}");
+            }
+            let mut cursor = Cursor::default();
+            // SAFETY: `validate` passed and the cursor starts at the beginning.
+            let status = unsafe {
+                run::<false>(opcodes, data, &mut cursor, input, output, config, observer)
+            };
+            status.unwrap_or_else(|| observer.stop_status())
+        }
+    }
+
+    /// [`ShiftAddEngine`] with every tape and opcode access bounds-checked, so a
+    /// bad program panics instead of reading out of bounds. Running the corpus
+    /// through it under Miri (`cargo miri run -- --checked <file>`) checks the
+    /// optimizer's output without the unchecked accesses hiding anything.
+    ///
+    /// Like `ShiftAddEngine`, `strict_tape` checks only net moves.
+    pub struct CheckedShiftAddEngine {}
+    impl BfEngine for CheckedShiftAddEngine {
+        const NAME: &'static str = "Shift add (checked)";
+        const DESCRIPTION: &'static str =
+            "shift add with every tape and opcode access bounds-checked";
+        const OPTIMIZATIONS: &'static [&'static str] = ShiftAddEngine::OPTIMIZATIONS;
+
+        type OPCODE = Opcode;
+
+        fn generate(
+            iter: impl Iterator<Item = BasicOpcode>,
+        ) -> Result<Vec<Self::OPCODE>, &'static str> {
+            ShiftAddEngine::generate(iter)
+        }
+
+        fn execute(
+            opcodes: &[Self::OPCODE],
+            data: &mut [u8; DATA_LEN],
+            input: &mut impl Read,
+            output: &mut impl Write,
+            config: &RunConfig,
+        ) -> ExitStatus {
+            Self::execute_observed(opcodes, data, input, output, config, &mut ())
+        }
+
+        fn execute_observed(
+            opcodes: &[Self::OPCODE],
+            data: &mut [u8; DATA_LEN],
+            input: &mut impl Read,
+            output: &mut impl Write,
+            config: &RunConfig,
+            observer: &mut impl Observer,
+        ) -> ExitStatus {
+            let mut cursor = Cursor::default();
+            // SAFETY: a `CHECKED` run has no preconditions.
+            let status =
+                unsafe { run::<true>(opcodes, data, &mut cursor, input, output, config, observer) };
+            status.unwrap_or_else(|| observer.stop_status())
+        }
+    }
+
+    /// Something observable that happened during [`execute_traced`].
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub enum TraceEvent {
+        /// The cell at `dp` was set to `value`.
+        Write { dp: usize, value: u8 },
+        /// The data pointer moved to `dp`.
+        Move { dp: usize },
+        /// A byte was written to the output.
+        Output(u8),
+    }
+
+    struct Tracer {
+        events: Vec<TraceEvent>,
+        cap: usize,
+    }
+
+    impl Tracer {
+        fn record(&mut self, event: TraceEvent) {
+            if self.events.len() < self.cap {
+                self.events.push(event);
+            }
+        }
+    }
+
+    impl Observer for Tracer {
+        fn write(&mut self, dp: usize, value: u8) {
+            self.record(TraceEvent::Write { dp, value });
+        }
+        fn moved(&mut self, dp: usize) {
+            self.record(TraceEvent::Move { dp });
+        }
+        fn output(&mut self, byte: u8) {
+            self.record(TraceEvent::Output(byte));
+        }
+    }
+
+    /// Like [`ShiftAddEngine::execute`], but also records every cell write,
+    /// pointer move and output byte so the run can be replayed without
+    /// executing it again.
+    ///
+    /// At most `cap` events are recorded; anything after that is dropped, while
+    /// execution continues normally.
+    pub fn execute_traced(
+        opcodes: &[Opcode],
+        data: &mut [u8; DATA_LEN],
+        input: &mut impl Read,
+        output: &mut impl Write,
+        config: &RunConfig,
+        cap: usize,
+    ) -> (ExitStatus, Vec<TraceEvent>) {
+        let mut tracer = Tracer {
+            events: Vec::new(),
+            cap,
+        };
+        let status =
+            ShiftAddEngine::execute_observed(opcodes, data, input, output, config, &mut tracer);
+        (status, tracer.events)
+    }
+
+    /// Where execution is: the program counter and the data pointer.
+    #[derive(Copy, Clone, Default, Debug, Eq, PartialEq)]
+    pub struct Cursor {
+        pub pc: usize,
+        pub dp: usize,
+    }
+
+    /// Dispatch loop shared by [`ShiftAddEngine::execute`] and [`Interpreter`].
+    ///
+    /// Returns `None` if `observer` paused execution before the program stopped.
+    ///
+    /// # Safety
+    /// Unless `CHECKED`, `opcodes` must pass [`validate`] and `cursor` must
+    /// point inside `opcodes` and the tape.
+    #[inline(always)]
+    unsafe fn run<const CHECKED: bool>(
+        opcodes: &[Opcode],
+        data: &mut [u8; DATA_LEN],
+        cursor: &mut Cursor,
+        input: &mut impl Read,
+        output: &mut impl Write,
+        config: &RunConfig,
+        observer: &mut impl Observer,
+    ) -> Option<ExitStatus> {
+        let Cursor { mut pc, mut dp } = *cursor;
+
+        // Bounds checks are only left out when not `CHECKED`.
+        macro_rules! cell {
+            ($i:expr) => {
+                if CHECKED {
+                    &mut data[$i]
+                } else {
+                    data.get_unchecked_mut($i)
+                }
+            };
+        }
+        macro_rules! opcode {
+            ($pc:expr) => {
+                if CHECKED {
+                    &opcodes[$pc]
+                } else {
+                    opcodes.get_unchecked($pc)
+                }
+            };
+        }
+
+        let status = 'run: loop {
+            macro_rules! shift {
+                ($dp:expr, $i:expr) => {
+                    match shift_pointer($dp, $i as _, config) {
+                        Ok(dp) => dp,
+                        Err(status) => break 'run Some(status),
+                    }
+                };
+            }
+            // The opcodes a `Block` may contain.
+            macro_rules! straight_line {
+                ($opcode:expr) => {
+                    match $opcode {
+                        Opcode::AddRight(a, i) => {
+                            let (value, carry) = cell!(dp).overflowing_add(*a);
+                            *cell!(dp) = value;
+                            if *a != 0 {
+                                observer.write(dp, value);
+                                // `a` is really signed: adding 255 is subtracting
+                                // 1, which wraps exactly when it doesn't carry.
+                                if carry == (*a < 128) {
+                                    observer.wrapped(dp);
+                                }
+                            }
+                            dp = shift!(dp, *i);
+                            if *i != 0 {
+                                observer.moved(dp);
+                            }
+                        }
+                        Opcode::SetRight(a, i) => {
+                            *cell!(dp) = *a;
+                            observer.write(dp, *a);
+                            dp = shift!(dp, *i);
+                            if *i != 0 {
+                                observer.moved(dp);
+                            }
+                            //*cell!(dp) = 0;
+                        }
+                        // Like the loop, never looks at `to` on a zero, which
+                        // matters with `strict_tape`.
+                        Opcode::AddTo(i) if *cell!(dp) != 0 => {
+                            let to = shift!(dp, *i);
+
+                            let tmp = *cell!(dp);
+
+                            let (value, wrapped) = cell!(to).overflowing_add(tmp);
+                            *cell!(to) = value;
+                            *cell!(dp) = 0;
+                            observer.write(to, value);
+                            observer.write(dp, 0);
+                            if wrapped {
+                                observer.wrapped(to);
+                            }
+                        }
+                        Opcode::AddTo(_) => (),
+                        Opcode::SubTo(i) if *cell!(dp) != 0 => {
+                            let to = shift!(dp, *i);
+
+                            let tmp = *cell!(dp);
+
+                            let (value, wrapped) = cell!(to).overflowing_sub(tmp);
+                            *cell!(to) = value;
+                            *cell!(dp) = 0;
+                            observer.write(to, value);
+                            observer.write(dp, 0);
+                            if wrapped {
+                                observer.wrapped(to);
+                            }
+                        }
+                        Opcode::SubTo(_) => (),
+                        Opcode::MoveTo(i) => {
+                            let to = shift!(dp, *i);
+
+                            let tmp = *cell!(dp);
+
+                            let (value, wrapped) = cell!(to).overflowing_add(tmp);
+                            *cell!(to) = value;
+                            *cell!(dp) = 0;
+                            observer.write(to, value);
+                            observer.write(dp, 0);
+                            if wrapped {
+                                observer.wrapped(to);
+                            }
+                            dp = to;
+                            observer.moved(dp);
+                        }
+                        _ => unreachable!("not a straight-line opcode"),
+                    }
+                };
+            }
+            //let Some(opcode) = opcodes.get(pc) else { break };
+            // What the unchecked accesses rely on, so an optimizer bug panics in
+            // debug builds instead of being undefined behaviour.
+            debug_assert!(pc < opcodes.len(), "pc {pc} past the end of the program");
+            debug_assert!(dp < DATA_LEN, "dp {dp} off the tape");
+            unsafe {
+                let opcode = opcode!(pc);
+                observer.opcode(pc);
+
+                match opcode {
+                    Opcode::AddRight(..)
+                    | Opcode::SetRight(..)
+                    | Opcode::AddTo(_)
+                    | Opcode::SubTo(_)
+                    | Opcode::MoveTo(_) => straight_line!(opcode),
+                    Opcode::Block(count) => {
+                        debug_assert!(
+                            pc + (*count as usize) < opcodes.len(),
+                            "block at {pc} too long"
+                        );
+                        for inner in pc + 1..=pc + *count as usize {
+                            observer.opcode(inner);
+                            straight_line!(opcode!(inner));
+                        }
+                        pc += *count as usize;
+                    }
+                    Opcode::BranchZero(i) => {
+                        debug_assert_eq!(
+                            opcodes.get(*i as usize),
+                            Some(&Opcode::BranchNotZero(pc as u32)),
+                            "BranchZero at {pc} doesn't pair with {i}"
+                        );
+                        if *cell!(dp) == 0 {
+                            pc = *i as _;
+                        }
+                    }
+                    Opcode::BranchNotZero(i) => {
+                        debug_assert_eq!(
+                            opcodes.get(*i as usize),
+                            Some(&Opcode::BranchZero(pc as u32)),
+                            "BranchNotZero at {pc} doesn't pair with {i}"
+                        );
+                        if *cell!(dp) != 0 {
+                            pc = *i as _;
+                        }
+                    }
+                    Opcode::Dot => {
+                        let _ = output.write(&[*cell!(dp)]);
+                        observer.output(*cell!(dp));
+                    }
+                    Opcode::Comma => {
+                        let _ = output.flush();
+                        let Some(c) = read_byte(input) else {
+                            break 'run Some(ExitStatus::Halted);
+                        };
+                        *cell!(dp) = c;
+                        observer.write(dp, c);
+                    }
+                    Opcode::EmitConst(byte, count) => {
+                        for _ in 0..*count {
+                            let _ = output.write(&[*byte]);
+                            observer.output(*byte);
+                        }
+                    }
+                    Opcode::Seek(i) => {
+                        for moved in 0.. {
+                            if *cell!(dp) == 0 {
+                                break;
+                            }
+                            if moved == config.max_seek {
+                                break 'run Some(ExitStatus::SeekLimit);
+                            }
+                            dp = shift!(dp, *i);
+                            observer.moved(dp);
+                        }
+                    }
+                    Opcode::Copy(dst, temp) => {
+                        let from = shift!(dp, *temp);
+
+                        let tmp = *cell!(dp);
+
+                        // The first loop, and with it `dst`, is skipped on a zero.
+                        if tmp != 0 {
+                            let to = shift!(dp, *dst);
+                            *cell!(to) = cell!(to).wrapping_add(tmp);
+                            observer.write(to, *cell!(to));
+                        }
+                        *cell!(dp) = tmp.wrapping_add(*cell!(from));
+                        *cell!(from) = 0;
+                        observer.write(dp, *cell!(dp));
+                        observer.write(from, 0);
+                        dp = from;
+                        observer.moved(dp);
+                    }
+                    Opcode::Exit => {
+                        break 'run Some(ExitStatus::Halted);
+                    }
+                }
+            }
+            pc += 1;
+            if observer.pause(data, dp) {
+                break 'run None;
+            }
+        };
+
+        *cursor = Cursor { pc, dp };
+        status
+    }
+
+    /// Finds a top-level loop that is certainly entered and can never exit, and
+    /// replaces everything after it with `Exit`. Returns how many opcodes were
+    /// removed.
+    ///
+    /// Cell values are followed from the start of the program (where every cell
+    /// is `config.tape_fill`) up to the first loop that can't be decided.
+    pub fn remove_dead_code(opcodes: &mut Vec<Opcode>, config: &RunConfig) -> usize {
+        use Opcode::*;
+        let mut cells = KnownCells::new(config);
+        let mut pc = 0;
+        while let Some(&opcode) = opcodes.get(pc) {
+            match opcode {
+                BranchZero(close) => {
+                    let close = close as usize;
+                    match cells.get(cells.p) {
+                        Some(0) => {}
+                        Some(_) if never_exits(&opcodes[pc + 1..close]) => {
+                            let removed = opcodes.len().saturating_sub(close + 2);
+                            opcodes.truncate(close + 1);
+                            opcodes.push(Exit);
+                            return removed;
+                        }
+                        _ => cells.forget(),
+                    }
+                    pc = close;
+                }
+                BranchNotZero(_) | Exit => break,
+                _ => cells.step(opcode),
+            }
+            pc += 1;
+        }
+        0
+    }
+
+    /// Replaces top-level loops whose counter is known on entry, like the
+    /// `++++++++[>++++++++<-]` many programs start with, by the stores they
+    /// would have done, and `.` on a known cell by `EmitConst`. Transfers like
+    /// `>+++[-<+>]<` fold as well. Only loops of `AddRight`s that end on the
+    /// counter and step it by one are folded, and none under `strict_tape`,
+    /// where a move in the loop could stop the run. Returns how many loops were
+    /// folded.
+    ///
+    /// Assumes the program starts on a fresh tape, every cell
+    /// `config.tape_fill`. `generate` does the same without that assumption, so
+    /// there only cells the program set itself count as known.
+    pub fn fold_constant_loops(opcodes: &mut Vec<Opcode>, config: &RunConfig) -> usize {
+        if config.strict_tape {
+            return 0;
+        }
+        fold_constants(opcodes, KnownCells::new(config), None).loops
+    }
+
+    /// What [`fold_constants`] replaced.
+    #[derive(Default)]
+    struct Folded {
+        loops: usize,
+        outputs: usize,
+    }
+
+    /// [`fold_constant_loops`] starting from `cells`, keeping `spans` in step.
+    fn fold_constants(
+        opcodes: &mut Vec<Opcode>,
+        mut cells: KnownCells,
+        spans: Option<&mut Vec<SourceSpan>>,
+    ) -> Folded {
+        use Opcode::*;
+        let mut done = Folded::default();
+        // A `Block` counts the opcodes after it, which folding would change.
+        if opcodes.iter().any(|opcode| matches!(opcode, Block(_))) {
+            return done;
+        }
+        let mut folded = Vec::with_capacity(opcodes.len());
+        let mut folded_spans = spans.as_ref().map(|_| Vec::with_capacity(opcodes.len()));
+        // Where each old opcode ended up, for fixing the branches afterwards.
+        let mut moved_to = Vec::with_capacity(opcodes.len());
+        let mut following = true;
+        let mut pc = 0;
+        while let Some(&opcode) = opcodes.get(pc) {
+            let mut len = 1;
+            let replacement = match opcode {
+                _ if !following => None,
+                BranchZero(close) => {
+                    len = close as usize + 1 - pc;
+                    let counter = cells.get(cells.p);
+                    let body = &opcodes[pc + 1..close as usize];
+                    let replacement = counter.and_then(|value| fold_loop(&mut cells, body, value));
+                    if replacement.is_none() && counter != Some(0) {
+                        cells.forget();
+                    }
+                    replacement
+                }
+                AddTo(_) | SubTo(_) | MoveTo(_) => {
+                    let replacement = fold_transfer(&cells, opcode);
+                    cells.step(opcode);
+                    replacement
+                }
+                Dot => cells.get(cells.p).map(|byte| vec![EmitConst(byte, 1)]),
+                BranchNotZero(_) | Exit => {
+                    following = false;
+                    None
+                }
+                _ => {
+                    cells.step(opcode);
+                    None
+                }
+            };
+            let span = spans.as_ref().map(|spans| SourceSpan {
+                start: spans[pc].start,
+                end: spans[pc + len - 1].end,
+            });
+            match replacement {
+                Some(replacement) => {
+                    moved_to.extend(std::iter::repeat_n(folded.len(), len));
+                    if opcode == Dot {
+                        done.outputs += 1;
+                    } else {
+                        done.loops += 1;
+                    }
+                    for opcode in replacement {
+                        push_folded(&mut folded, folded_spans.as_mut(), opcode, span);
+                    }
+                }
+                // Loops are kept whole, and tidying only ever looks at opcodes
+                // outside them.
+                None if len == 1 && following => {
+                    moved_to.push(folded.len());
+                    push_folded(&mut folded, folded_spans.as_mut(), opcode, span);
+                }
+                None => {
+                    moved_to.extend(folded.len()..folded.len() + len);
+                    folded.extend_from_slice(&opcodes[pc..pc + len]);
+                    if let (Some(folded_spans), Some(spans)) = (&mut folded_spans, &spans) {
+                        folded_spans.extend_from_slice(&spans[pc..pc + len]);
+                    }
+                }
+            }
+            pc += len;
+        }
+        if done.loops + done.outputs == 0 {
+            return done;
+        }
+        for opcode in &mut folded {
+            if let BranchZero(target) | BranchNotZero(target) = opcode {
+                *target = moved_to[*target as usize].try_into().unwrap();
+            }
+        }
+        *opcodes = folded;
+        if let (Some(spans), Some(folded_spans)) = (spans, folded_spans) {
+            *spans = folded_spans;
+        }
+        done
+    }
+
+    /// Appends `opcode` after a fold, merging it with the one before where
+    /// they touch the same cell: a plain move joins the move before it, a set
+    /// drops the add or set it overwrites, and equal `EmitConst`s join up.
+    fn push_folded(
+        folded: &mut Vec<Opcode>,
+        spans: Option<&mut Vec<SourceSpan>>,
+        opcode: Opcode,
+        span: Option<SourceSpan>,
+    ) {
+        use Opcode::*;
+        let merged = match (folded.last_mut(), opcode) {
+            (Some(AddRight(_, s) | SetRight(_, s)), AddRight(0, t))
+                if s.checked_add(t).is_some() =>
+            {
+                *s += t;
+                true
+            }
+            (Some(EmitConst(byte, count)), EmitConst(new, 1))
+                if *byte == new && *count < u16::MAX =>
+            {
+                *count += 1;
+                true
+            }
+            (Some(AddRight(_, 0) | SetRight(_, 0)), SetRight(..)) => {
+                *folded.last_mut().unwrap() = opcode;
+                true
+            }
+            _ => false,
+        };
+        if !merged {
+            folded.push(opcode);
+        }
+        if let (Some(spans), Some(span)) = (spans, span) {
+            match spans.last_mut() {
+                Some(last) if merged => last.end = span.end,
+                _ => spans.push(span),
+            }
+        }
+    }
+
+    /// The stores a loop entered with its counter at `value` amounts to, also
+    /// applying them to `cells`. `None` if [`loop_adds`] can't tell.
+    fn fold_loop(cells: &mut KnownCells, body: &[Opcode], value: u8) -> Option<Vec<Opcode>> {
+        use Opcode::*;
+        let adds = loop_adds(body, value)?;
+        // Clear the counter, then visit each cell and come back. Cells with a
+        // known value are set rather than added to.
+        let mut replacement = vec![SetRight(0, adds.first().map_or(0, |&(o, _)| o))];
+        for (i, &(offset, add)) in adds.iter().enumerate() {
+            let next = adds.get(i + 1).map_or(0, |&(next, _)| next);
+            let shift = (next as isize - offset as isize).try_into().ok()?;
+            replacement.push(match cells.get(cells.p + offset as isize) {
+                Some(value) => SetRight(value.wrapping_add(add), shift),
+                None => AddRight(add, shift),
+            });
+        }
+        for &(offset, add) in &adds {
+            let to = cells.p + offset as isize;
+            cells.set(to, cells.get(to).map(|value| value.wrapping_add(add)));
+        }
+        cells.set(cells.p, Some(0));
+        Some(replacement)
+    }
+
+    /// The stores an `AddTo`, `SubTo` or `MoveTo` with a known source amounts
+    /// to, without applying them to `cells`.
+    fn fold_transfer(cells: &KnownCells, opcode: Opcode) -> Option<Vec<Opcode>> {
+        use Opcode::*;
+        let (AddTo(o) | SubTo(o) | MoveTo(o)) = opcode else {
+            return None;
+        };
+        let x = cells.get(cells.p)?;
+        let back = if let MoveTo(_) = opcode {
+            0
+        } else {
+            o.checked_neg()?
+        };
+        if x == 0 {
+            // The loop never runs.
+            return Some(if let MoveTo(_) = opcode {
+                vec![AddRight(0, o)]
+            } else {
+                vec![]
+            });
+        }
+        let add = if let SubTo(_) = opcode {
+            x.wrapping_neg()
+        } else {
+            x
+        };
+        let store = match cells.get(cells.p + o as isize) {
+            Some(y) => SetRight(y.wrapping_add(add), back),
+            None => AddRight(add, back),
+        };
+        Some(vec![SetRight(0, o), store])
+    }
+
+    /// For a loop entered with its counter at `value`, what it adds to each
+    /// other cell, by offset. `None` if it isn't a loop [`fold_constant_loops`]
+    /// folds, or reaches further than an `i16` shift.
+    fn loop_adds(body: &[Opcode], value: u8) -> Option<Vec<(i16, u8)>> {
+        use Opcode::*;
+        let mut offset: i16 = 0;
+        let mut deltas: Vec<(i16, u8)> = Vec::new();
+        for opcode in body {
+            let AddRight(add, shift) = *opcode else {
+                return None;
+            };
+            match deltas.iter_mut().find(|(o, _)| *o == offset) {
+                Some((_, delta)) => *delta = delta.wrapping_add(add),
+                None => deltas.push((offset, add)),
+            }
+            offset = offset.checked_add(shift)?;
+        }
+        if offset != 0 {
+            return None;
+        }
+        let iterations = match deltas.iter().find(|&&(o, _)| o == 0) {
+            Some((_, 255)) => value,
+            Some((_, 1)) => value.wrapping_neg(),
+            _ => return None,
+        };
+        let mut adds: Vec<(i16, u8)> = deltas
+            .into_iter()
+            .filter(|&(o, _)| o != 0)
+            .map(|(o, delta)| (o, delta.wrapping_mul(iterations)))
+            .filter(|&(_, add)| add != 0)
+            .collect();
+        adds.sort();
+        Some(adds)
+    }
+
+    /// Cell values followed through straight-line code, relative to where the
+    /// pointer started.
+    struct KnownCells {
+        /// `None` for cells whose value isn't known.
+        known: HashMap<isize, Option<u8>>,
+        /// The value of every cell not in `known`.
+        rest: Option<u8>,
+        p: isize,
+    }
+
+    impl KnownCells {
+        /// At the start of the program, where every cell is `config.tape_fill`.
+        fn new(config: &RunConfig) -> Self {
+            Self {
+                known: HashMap::new(),
+                rest: Some(config.tape_fill),
+                p: 0,
+            }
+        }
+
+        /// On a tape nothing is known about.
+        fn unknown() -> Self {
+            Self {
+                known: HashMap::new(),
+                rest: None,
+                p: 0,
+            }
+        }
+
+        fn get(&self, offset: isize) -> Option<u8> {
+            *self.known.get(&cell(offset)).unwrap_or(&self.rest)
+        }
+
+        fn set(&mut self, offset: isize, value: Option<u8>) {
+            self.known.insert(cell(offset), value);
+        }
+
+        /// After a loop that ran: anything could have happened in it, except
+        /// that it left the pointer on a zero.
+        fn forget(&mut self) {
+            self.known = HashMap::from([(0, Some(0))]);
+            self.rest = None;
+            self.p = 0;
+        }
+
+        /// Follows an opcode that doesn't branch.
+        fn step(&mut self, opcode: Opcode) {
+            use Opcode::*;
+            let p = self.p;
+            match opcode {
+                AddRight(a, s) => {
+                    self.set(p, self.get(p).map(|v| v.wrapping_add(a)));
+                    self.p += s as isize;
+                }
+                SetRight(v, s) => {
+                    self.set(p, Some(v));
+                    self.p += s as isize;
+                }
+                AddTo(o) | SubTo(o) | MoveTo(o) => {
+                    let to = p + o as isize;
+                    let value = match (self.get(p), self.get(to)) {
+                        (Some(x), Some(y)) if matches!(opcode, SubTo(_)) => Some(y.wrapping_sub(x)),
+                        (Some(x), Some(y)) => Some(y.wrapping_add(x)),
+                        _ => None,
+                    };
+                    self.set(to, value);
+                    self.set(p, Some(0));
+                    if let MoveTo(_) = opcode {
+                        self.p = to;
+                    }
+                }
+                Copy(dst, temp) => {
+                    self.set(p, None);
+                    self.set(p + dst as isize, None);
+                    self.set(p + temp as isize, Some(0));
+                    self.p += temp as isize;
+                }
+                Comma => self.set(p, None),
+                Seek(_) => self.forget(),
+                Dot | EmitConst(..) | Block(_) | BranchZero(_) | BranchNotZero(_) | Exit => (),
+            }
+        }
+    }
+
+    fn cell(offset: isize) -> isize {
+        offset.rem_euclid(DATA_LEN as isize)
+    }
+
+    /// Whether any opcode reads input.
+    pub fn reads_input(opcodes: &[Opcode]) -> bool {
+        opcodes.iter().any(|opcode| matches!(opcode, Opcode::Comma))
+    }
+
+    /// Whether any opcode writes output.
+    pub fn writes_output(opcodes: &[Opcode]) -> bool {
+        opcodes
+            .iter()
+            .any(|opcode| matches!(opcode, Opcode::Dot | Opcode::EmitConst(..)))
+    }
+
+    /// Whether the program certainly halts because nothing in it can repeat: no
+    /// loops and no `Seek`. Says nothing about programs that do have them.
+    pub fn is_obviously_terminating(opcodes: &[Opcode]) -> bool {
+        !opcodes.iter().any(|opcode| {
+            matches!(
+                opcode,
+                Opcode::BranchZero(_) | Opcode::BranchNotZero(_) | Opcode::Seek(_)
+            )
+        })
+    }
+
+    /// Returns the pcs of opcodes no execution can reach: anything after an
+    /// `Exit`, or after a loop that can only be left by skipping it entirely.
+    pub fn unreachable_opcodes(opcodes: &[Opcode]) -> Vec<usize> {
+        use Opcode::*;
+        let mut reached = vec![false; opcodes.len()];
+        let mut pending = vec![0];
+        while let Some(pc) = pending.pop() {
+            if pc >= opcodes.len() || reached[pc] {
+                continue;
+            }
+            reached[pc] = true;
+            match opcodes[pc] {
+                Exit => (),
+                BranchZero(close) => pending.extend([pc + 1, close as usize + 1]),
+                BranchNotZero(open) => {
+                    let open = open as usize;
+                    pending.push(open + 1);
+                    if open >= pc || !never_exits(&opcodes[open + 1..pc]) {
+                        pending.push(pc + 1);
+                    }
+                }
+                Block(count) => {
+                    let end = (pc + count as usize).min(opcodes.len() - 1);
+                    reached[pc + 1..=end].fill(true);
+                    pending.push(end + 1);
+                }
+                _ => pending.push(pc + 1),
+            }
+        }
+        (0..opcodes.len()).filter(|&pc| !reached[pc]).collect()
+    }
+
+    /// Strips the opcodes [`unreachable_opcodes`] finds, keeping both halves of
+    /// a loop if either is reachable. Returns how many were removed. Panics if
+    /// `opcodes` doesn't pass [`validate`].
+    pub fn remove_unreachable(opcodes: &mut Vec<Opcode>) -> usize {
+        use Opcode::*;
+        if let Err(e) = validate(opcodes) {
+            panic!("remove_unreachable on a malformed program: {e}");
+        }
+        let mut removed = vec![false; opcodes.len()];
+        for pc in unreachable_opcodes(opcodes) {
+            removed[pc] = true;
+        }
+        for pc in 0..opcodes.len() {
+            if let BranchZero(other) | BranchNotZero(other) = opcodes[pc] {
+                if !removed[other as usize] {
+                    removed[pc] = false;
+                }
+            }
+        }
+        remove_marked(opcodes, &removed);
+        if let Err(e) = validate(opcodes) {
+            panic!("remove_unreachable produced a malformed program: {e}");
+        }
+        removed.iter().filter(|&&r| r).count()
+    }
+
+    /// Whether a loop with this body keeps its control cell and returns to it
+    /// every iteration, so once entered it runs forever.
+    fn never_exits(body: &[Opcode]) -> bool {
+        use Opcode::*;
+        let cell = |offset: isize| offset.rem_euclid(DATA_LEN as isize);
+        let mut offset: isize = 0;
+        for &opcode in body {
+            let writes = match opcode {
+                AddRight(a, s) => {
+                    let writes = a != 0 && cell(offset) == 0;
+                    offset += s as isize;
+                    writes
+                }
+                SetRight(_, s) => {
+                    let writes = cell(offset) == 0;
+                    offset += s as isize;
+                    writes
+                }
+                AddTo(o) | SubTo(o) => cell(offset) == 0 || cell(offset + o as isize) == 0,
+                MoveTo(o) => {
+                    let writes = cell(offset) == 0 || cell(offset + o as isize) == 0;
+                    offset += o as isize;
+                    writes
+                }
+                Dot | EmitConst(..) | Block(_) => false,
+                _ => return false,
+            };
+            if writes {
+                return false;
+            }
+        }
+        cell(offset) == 0
+    }
+
+    /// Prints every opcode with how many times it ran and its weighted cost,
+    /// indented by loop depth, to stderr so it stays out of the program's output.
+    pub fn print_profile(opcodes: &[Opcode], profile: &Profile, indent: Indent) {
+        let mut increment: usize = 0;
+        for (line, el) in opcodes.iter().enumerate() {
+            let count = profile.counts.get(line).unwrap_or(&0);
+            let cost = profile.costs.get(line).unwrap_or(&0);
+            if let Opcode::BranchNotZero(_) = el {
+                increment = increment.saturating_sub(1)
+            }
+            eprintln!(
+                "{line:>5}: {count:>8} {cost:>9} {}{el:?}",
+                indent.at(increment)
+            );
+            if let Opcode::BranchZero(_) = el {
+                increment += 1
+            }
+        }
+        if let Some((pc, count)) = profile.counts.iter().enumerate().max_by_key(|a| a.1) {
+            eprintln!("hottest: {pc} ran {count} times");
+        }
+    }
+
+    /// Watches a run to estimate how many plain Brainfuck commands the
+    /// unoptimized program would have executed, to put a number on what the
+    /// optimizer saved. Fused loops are weighted by their trip count, which
+    /// comes from a copy of the tape kept up to date from the observer hooks.
+    pub struct BasicOpEstimate {
+        tape: Box<[u8; DATA_LEN]>,
+        dp: usize,
+        pc: usize,
+        counts: Vec<u64>,
+        /// Sum of the current cell's value each time the opcode started.
+        entries: Vec<u64>,
+        moves: Vec<u64>,
+    }
+
+    impl BasicOpEstimate {
+        pub fn new(config: &RunConfig) -> Self {
+            Self {
+                tape: new_tape(config),
+                dp: 0,
+                pc: 0,
+                counts: Vec::new(),
+                entries: Vec::new(),
+                moves: Vec::new(),
+            }
+        }
+
+        /// Opcodes the engine dispatched.
+        pub fn dispatched(&self) -> u64 {
+            self.counts.iter().sum()
+        }
+
+        /// Commands the unoptimized program would have run, going by what
+        /// each opcode of `opcodes` was compiled from: `[->+<]` entered on a 3
+        /// is `1 + 3 * 5`, for example. Commands the optimizer dropped outright,
+        /// like `+-` or the adds before a `[-]`, aren't counted, and every clear
+        /// is taken to be `[-]`.
+        pub fn basic_ops(&self, opcodes: &[Opcode]) -> u64 {
+            use Opcode::*;
+            // The shorter of `+` and `-` that adds `a`.
+            let add = |a: u8| a.min(a.wrapping_neg()) as u64;
+            let shift = |s: i16| s.unsigned_abs() as u64;
+            let mut total = 0;
+            for (pc, &count) in self.counts.iter().enumerate() {
+                let entry = self.entries[pc];
+                total += match opcodes.get(pc) {
+                    Some(&AddRight(a, s)) => count * (add(a) + shift(s)),
+                    // `[-]` first, then the adds.
+                    Some(&SetRight(v, s)) => count * (1 + add(v) + shift(s)) + entry * 2,
+                    Some(AddTo(x) | SubTo(x)) => count + entry * (3 + 2 * shift(*x)),
+                    Some(&MoveTo(x)) => count * (1 + shift(x)) + entry * (3 + 2 * shift(x)),
+                    Some(&Seek(x)) => count + self.moves[pc] * (1 + shift(x)),
+                    // Two transfers, the second from `temp` back to the start.
+                    Some(&Copy(dst, temp)) => {
+                        let out = 4 + 2 * shift(dst).max(shift(temp));
+                        count * (2 + shift(temp)) + entry * (out + 3 + 2 * shift(temp))
+                    }
+                    Some(&EmitConst(_, n)) => count * n as u64,
+                    Some(BranchZero(_) | BranchNotZero(_) | Dot | Comma) => count,
+                    Some(Block(_) | Exit) | None => 0,
+                };
+            }
+            total
+        }
+
+        /// How many basic commands each dispatched opcode stood for.
+        pub fn speedup(&self, opcodes: &[Opcode]) -> f64 {
+            self.basic_ops(opcodes) as f64 / self.dispatched().max(1) as f64
+        }
+    }
+
+    impl Observer for BasicOpEstimate {
+        fn opcode(&mut self, pc: usize) {
+            if pc >= self.counts.len() {
+                self.counts.resize(pc + 1, 0);
+                self.entries.resize(pc + 1, 0);
+                self.moves.resize(pc + 1, 0);
+            }
+            self.counts[pc] += 1;
+            self.entries[pc] += self.tape[self.dp] as u64;
+            self.pc = pc;
+        }
+        fn write(&mut self, dp: usize, value: u8) {
+            self.tape[dp] = value;
+        }
+        fn moved(&mut self, dp: usize) {
+            self.dp = dp;
+            self.moves[self.pc] += 1;
+        }
+    }
+
+    /// How many opcodes of each variant a compiled program has.
+    #[derive(Clone, Default, Debug)]
+    pub struct OpcodeStats {
+        pub counts: [usize; Opcode::NAMES.len()],
+    }
+
+    impl OpcodeStats {
+        pub fn from_opcodes(opcodes: &[Opcode]) -> Self {
+            let mut stats = Self::default();
+            for opcode in opcodes {
+                stats.counts[opcode.index()] += 1;
+            }
+            stats
+        }
+    }
+
+    /// How many times each opcode variant ran, summed over any number of runs.
+    #[derive(Clone, Default, Debug)]
+    pub struct Coverage {
+        pub hits: [usize; Opcode::NAMES.len()],
+    }
+
+    impl Coverage {
+        /// Adds the counts of one run of `opcodes` profiled into `profile`.
+        pub fn record(&mut self, opcodes: &[Opcode], profile: &Profile) {
+            for (opcode, count) in opcodes.iter().zip(&profile.counts) {
+                self.hits[opcode.index()] += count;
+            }
+        }
+
+        /// Variants no recorded run executed.
+        pub fn never_hit(&self) -> impl Iterator<Item = &'static str> + '_ {
+            Opcode::NAMES
+                .iter()
+                .zip(&self.hits)
+                .filter(|(_, &hits)| hits == 0)
+                .map(|(&name, _)| name)
+        }
+    }
+
+    impl std::fmt::Display for Coverage {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            for (name, hits) in Opcode::NAMES.iter().zip(&self.hits) {
+                writeln!(f, "{name:>14}: {hits}")?;
+            }
+            for name in self.never_hit() {
+                writeln!(f, "{name} never executed")?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Everything needed to resume an [`Interpreter`] later, tied to the program
+    /// it was taken from.
+    #[derive(Clone, Debug, Eq, PartialEq)]
+    pub struct StateSnapshot {
+        /// Fingerprint of the opcodes, so a snapshot isn't restored into a
+        /// different program.
+        pub program: u64,
+        pub cursor: Cursor,
+        pub input_pos: usize,
+        pub tape: Vec<u8>,
+    }
+
+    impl StateSnapshot {
+        const MAGIC: &'static [u8; 8] = b"BFSTATE1";
+        const HEADER_LEN: usize = 8 + 4 * 8;
+
+        /// A fixed-size binary form: magic, program fingerprint, pc, dp and
+        /// input position as little-endian `u64`s, then the tape.
+        pub fn to_bytes(&self) -> Vec<u8> {
+            let mut bytes = Vec::with_capacity(Self::HEADER_LEN + self.tape.len());
+            bytes.extend_from_slice(Self::MAGIC);
+            for field in [
+                self.program,
+                self.cursor.pc as u64,
+                self.cursor.dp as u64,
+                self.input_pos as u64,
+            ] {
+                bytes.extend_from_slice(&field.to_le_bytes());
+            }
+            bytes.extend_from_slice(&self.tape);
+            bytes
+        }
+
+        pub fn from_bytes(bytes: &[u8]) -> Result<Self, &'static str> {
+            if bytes.len() != Self::HEADER_LEN + DATA_LEN || !bytes.starts_with(Self::MAGIC) {
+                return Err("not a saved interpreter state");
+            }
+            let field = |i: usize| {
+                let start = 8 + i * 8;
+                u64::from_le_bytes(bytes[start..start + 8].try_into().unwrap())
+            };
+            Ok(Self {
+                program: field(0),
+                cursor: Cursor {
+                    pc: field(1) as usize,
+                    dp: field(2) as usize,
+                },
+                input_pos: field(3) as usize,
+                tape: bytes[Self::HEADER_LEN..].to_vec(),
+            })
+        }
+    }
+
+    /// FNV-1a over the opcodes' `Debug` text, which stays the same between
+    /// builds unlike `DefaultHasher`.
+    fn fingerprint(opcodes: &[Opcode]) -> u64 {
+        format!("{opcodes:?}")
+            .bytes()
+            .fold(0xcbf29ce484222325, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+            })
+    }
+
+    /// A [`ShiftAddEngine`] program with its own tape and input that can be
+    /// executed a step at a time and resumed.
+    pub struct Interpreter<W: Write> {
+        opcodes: Vec<Opcode>,
+        data: Box<[u8; DATA_LEN]>,
+        cursor: Cursor,
+        input: Vec<u8>,
+        input_pos: usize,
+        output: W,
+        config: RunConfig,
+    }
+
+    impl<W: Write> Interpreter<W> {
+        pub fn new(
+            iter: impl Iterator<Item = BasicOpcode>,
+            input: Vec<u8>,
+            output: W,
+            config: RunConfig,
+        ) -> Result<Self, &'static str> {
+            Ok(Self {
+                opcodes: ShiftAddEngine::generate(iter)?,
+                data: new_tape(&config),
+                cursor: Cursor::default(),
+                input,
+                input_pos: 0,
+                output,
+                config,
+            })
+        }
+
+        /// Executes a single opcode, returning why the program stopped if it did.
+        pub fn step(&mut self) -> Option<ExitStatus> {
+            self.run_until(|_, _| true)
+        }
+
+        /// Steps until `predicate` holds for the tape and data pointer, or the
+        /// program stops, in which case its exit status is returned.
+        ///
+        /// The predicate is checked after every opcode, so at least one opcode
+        /// is executed per call.
+        pub fn run_until(
+            &mut self,
+            predicate: impl Fn(&[u8; DATA_LEN], usize) -> bool,
+        ) -> Option<ExitStatus> {
+            struct Until<F>(F);
+            impl<F: Fn(&[u8; DATA_LEN], usize) -> bool> Observer for Until<F> {
+                fn pause(&mut self, data: &[u8; DATA_LEN], dp: usize) -> bool {
+                    (self.0)(data, dp)
+                }
+            }
+
+            let mut input = &self.input[self.input_pos..];
+            // SAFETY: `opcodes` came from `generate`, which always passes
+            // `validate`, and only `run` and the checked setters move the cursor.
+            let status = unsafe {
+                run::<false>(
+                    &self.opcodes,
+                    &mut self.data,
+                    &mut self.cursor,
+                    &mut input,
+                    &mut self.output,
+                    &self.config,
+                    &mut Until(predicate),
+                )
+            };
+            self.input_pos = self.input.len() - input.len();
+            status
+        }
+
+        pub fn opcodes(&self) -> &[Opcode] {
+            &self.opcodes
+        }
+
+        pub fn cursor(&self) -> Cursor {
+            self.cursor
+        }
+
+        pub fn output(&self) -> &W {
+            &self.output
+        }
+
+        pub fn tape(&self) -> &[u8] {
+            &self.data[..]
+        }
+
+        /// For setting up cells before a run or changing them between runs.
+        pub fn tape_mut(&mut self) -> &mut [u8] {
+            &mut self.data[..]
+        }
+
+        /// Moves the data pointer, which must be within the tape.
+        pub fn set_pointer(&mut self, dp: usize) {
+            assert!(dp < DATA_LEN, "data pointer {dp} is outside the tape");
+            self.cursor.dp = dp;
+        }
+
+        /// Starts the program over from its first opcode, keeping the tape and
+        /// data pointer as they are.
+        pub fn rewind(&mut self) {
+            self.cursor.pc = 0;
+        }
+
+        pub fn save_state(&self) -> StateSnapshot {
+            StateSnapshot {
+                program: fingerprint(&self.opcodes),
+                cursor: self.cursor,
+                input_pos: self.input_pos,
+                tape: self.data.to_vec(),
+            }
+        }
+
+        /// Picks up where `snapshot` left off. The input has to be the same one
+        /// the snapshot was taken with.
+        pub fn restore_state(&mut self, snapshot: &StateSnapshot) -> Result<(), &'static str> {
+            if snapshot.program != fingerprint(&self.opcodes) {
+                return Err("saved state is from a different program");
+            }
+            if snapshot.cursor.pc >= self.opcodes.len()
+                || snapshot.cursor.dp >= DATA_LEN
+                || snapshot.input_pos > self.input.len()
+                || snapshot.tape.len() != DATA_LEN
+            {
+                return Err("saved state doesn't fit this program and input");
+            }
+            self.cursor = snapshot.cursor;
+            self.input_pos = snapshot.input_pos;
+            self.data.copy_from_slice(&snapshot.tape);
+            Ok(())
+        }
+    }
+}
+
+pub use sparse_engine::SparseEngine;
+/// Runs [`ShiftAddEngine`] opcodes on a tape unbounded in both directions,
+/// for programs that range far but touch few cells.
+///
+/// Cells in `0..DATA_LEN` are the caller's `data`, as with every other engine;
+/// the ones outside it go in a `HashMap`. Each access checks which side it's
+/// on, so in `benches/handlers.rs` it runs about 3x slower than the dense tape
+/// on nested loops and 2x on writes spread across it.
+pub mod sparse_engine {
+    use super::shift_add_engine::Opcode;
+    use super::{
+        read_byte, BasicOpcode, BfEngine, ExitStatus, Observer, Read, RunConfig, ShiftAddEngine,
+        Write, DATA_LEN,
+    };
+    use std::collections::HashMap;
+
+    /// `data`, extended with the cells outside it.
+    struct Tape<'a> {
+        data: &'a mut [u8; DATA_LEN],
+        outside: HashMap<i64, u8>,
+        fill: u8,
+    }
+
+    impl Tape<'_> {
+        fn get(&self, dp: i64) -> u8 {
+            match on_tape(dp) {
+                Some(dp) => self.data[dp],
+                None => *self.outside.get(&dp).unwrap_or(&self.fill),
+            }
+        }
+
+        fn set(&mut self, dp: i64, value: u8) {
+            match on_tape(dp) {
+                Some(dp) => self.data[dp] = value,
+                None => {
+                    self.outside.insert(dp, value);
+                }
+            }
+        }
+    }
+
+    pub struct SparseEngine {}
+    impl BfEngine for SparseEngine {
+        const NAME: &'static str = "Sparse";
+        const DESCRIPTION: &'static str = "shift add on a tape unbounded in both directions";
+        const OPTIMIZATIONS: &'static [&'static str] = ShiftAddEngine::OPTIMIZATIONS;
+
+        type OPCODE = Opcode;
+
+        fn generate(
+            iter: impl Iterator<Item = BasicOpcode>,
+        ) -> Result<Vec<Self::OPCODE>, &'static str> {
+            ShiftAddEngine::generate(iter)
+        }
+
+        /// The pointer never wraps and `strict_tape` has no effect. Cells
+        /// outside `data` start out as `tape_fill` and are dropped at the end.
+        fn execute(
+            opcodes: &[Self::OPCODE],
+            data: &mut [u8; DATA_LEN],
+            input: &mut impl Read,
+            output: &mut impl Write,
+            config: &RunConfig,
+        ) -> ExitStatus {
+            Self::execute_observed(opcodes, data, input, output, config, &mut ())
+        }
+
+        /// `observer` only hears about cells in `data`: `write`, `moved` and
+        /// `wrapped` are skipped outside it, and `pause` gets `dp` clamped into
+        /// it.
+        fn execute_observed(
+            opcodes: &[Self::OPCODE],
+            data: &mut [u8; DATA_LEN],
+            input: &mut impl Read,
+            output: &mut impl Write,
+            config: &RunConfig,
+            observer: &mut impl Observer,
+        ) -> ExitStatus {
+            let mut tape = Tape {
+                data,
+                outside: HashMap::new(),
+                fill: config.tape_fill,
+            };
+            let mut pc: usize = 0;
+            let mut dp: i64 = 0;
+            macro_rules! set {
+                ($dp:expr, $value:expr) => {{
+                    let (dp, value) = ($dp, $value);
+                    tape.set(dp, value);
+                    if let Some(dp) = on_tape(dp) {
+                        observer.write(dp, value);
+                    }
+                }};
+            }
+            macro_rules! wrapped {
+                ($dp:expr) => {
+                    if let Some(dp) = on_tape($dp) {
+                        observer.wrapped(dp);
+                    }
+                };
+            }
+            macro_rules! shift {
+                ($i:expr) => {
+                    dp += $i as i64;
+                    if let Some(dp) = on_tape(dp) {
+                        observer.moved(dp);
+                    }
+                };
+            }
+            'run: loop {
+                let Some(opcode) = opcodes.get(pc) else {
+                    break ExitStatus::Halted;
+                };
+                observer.opcode(pc);
+                match *opcode {
+                    Opcode::AddRight(a, i) => {
+                        if a != 0 {
+                            let (value, carry) = tape.get(dp).overflowing_add(a);
+                            set!(dp, value);
+                            // `a` is really signed: adding 255 is subtracting
+                            // 1, which wraps exactly when it doesn't carry.
+                            if carry == (a < 128) {
+                                wrapped!(dp);
+                            }
+                        }
+                        if i != 0 {
+                            shift!(i);
+                        }
+                    }
+                    Opcode::SetRight(a, i) => {
+                        set!(dp, a);
+                        if i != 0 {
+                            shift!(i);
+                        }
+                    }
+                    // Like the loop, never touches the target on a zero.
+                    Opcode::AddTo(_) | Opcode::SubTo(_) if tape.get(dp) == 0 => (),
+                    Opcode::AddTo(i) | Opcode::SubTo(i) | Opcode::MoveTo(i) => {
+                        let (from, to) = (tape.get(dp), tape.get(dp + i as i64));
+                        let (sum, wrapped) = match opcode {
+                            Opcode::SubTo(_) => to.overflowing_sub(from),
+                            _ => to.overflowing_add(from),
+                        };
+                        set!(dp + i as i64, sum);
+                        set!(dp, 0);
+                        if wrapped {
+                            wrapped!(dp + i as i64);
+                        }
+                        if let Opcode::MoveTo(_) = opcode {
+                            shift!(i);
+                        }
+                    }
+                    Opcode::Seek(i) => {
+                        for moved in 0.. {
+                            if tape.get(dp) == 0 {
+                                break;
+                            }
+                            if moved == config.max_seek {
+                                break 'run ExitStatus::SeekLimit;
+                            }
+                            shift!(i);
+                        }
+                    }
+                    Opcode::Copy(dst, temp) => {
+                        let (to, from) = (dp + dst as i64, dp + temp as i64);
+                        let value = tape.get(dp);
+                        if value != 0 {
+                            set!(to, tape.get(to).wrapping_add(value));
+                        }
+                        set!(dp, value.wrapping_add(tape.get(from)));
+                        set!(from, 0);
+                        shift!(temp);
+                    }
+                    Opcode::BranchZero(i) => {
+                        if tape.get(dp) == 0 {
+                            pc = i as usize;
+                        }
+                    }
+                    Opcode::BranchNotZero(i) => {
+                        if tape.get(dp) != 0 {
+                            pc = i as usize;
+                        }
+                    }
+                    Opcode::Dot => {
+                        let _ = output.write(&[tape.get(dp)]);
+                        observer.output(tape.get(dp));
+                    }
+                    Opcode::Comma => {
+                        let _ = output.flush();
+                        let Some(c) = read_byte(input) else {
+                            break ExitStatus::Halted;
+                        };
+                        set!(dp, c);
+                    }
+                    Opcode::EmitConst(byte, count) => {
+                        for _ in 0..count {
+                            let _ = output.write(&[byte]);
+                            observer.output(byte);
+                        }
+                    }
+                    // The opcodes it groups follow it and run as usual.
+                    Opcode::Block(_) => (),
+                    Opcode::Exit => break ExitStatus::Halted,
+                }
+                pc += 1;
+                let clamped = dp.clamp(0, DATA_LEN as i64 - 1) as usize;
+                if observer.pause(tape.data, clamped) {
+                    break observer.stop_status();
+                }
+            }
+        }
+    }
+
+    /// Where `dp` is in `data`, if it's there at all.
+    fn on_tape(dp: i64) -> Option<usize> {
+        usize::try_from(dp).ok().filter(|&dp| dp < DATA_LEN)
+    }
+}
+
+pub use soa_engine::SoaProgram;
+/// [`ShiftAddEngine`] opcodes stored as two parallel arrays, the kind of each
+/// opcode and its operands packed into an `i32`, instead of one array of enums.
+///
+/// Not a [`BfEngine`] since the program isn't a single `Vec`. It ran within a
+/// few percent of `ShiftAddEngine` on a nested-loop benchmark and about 60%
+/// slower on a long `Seek`, so the enum layout stays the default.
+pub mod soa_engine {
+    use super::shift_add_engine::Opcode;
+    use super::{read_byte, shift_pointer, ExitStatus, Read, RunConfig, Write, DATA_LEN};
+
+    const BRANCH_ZERO: u8 = 0;
+    const BRANCH_NOT_ZERO: u8 = 1;
+    const ADD_RIGHT: u8 = 2;
+    const SET_RIGHT: u8 = 3;
+    const ADD_TO: u8 = 4;
+    const SUB_TO: u8 = 5;
+    const MOVE_TO: u8 = 6;
+    const SEEK: u8 = 7;
+    const DOT: u8 = 8;
+    const COMMA: u8 = 9;
+    const EMIT_CONST: u8 = 10;
+    const COPY: u8 = 11;
+    const BLOCK: u8 = 12;
+    const EXIT: u8 = 13;
+
+    #[derive(Clone, Debug, Default)]
+    pub struct SoaProgram {
+        kinds: Vec<u8>,
+        operands: Vec<i32>,
+    }
+
+    /// Two operands in one `i32`, `high` in the upper half.
+    fn pack(high: u16, low: u16) -> i32 {
+        ((high as u32) << 16 | low as u32) as i32
+    }
+
+    fn high(operand: i32) -> u16 {
+        (operand as u32 >> 16) as u16
+    }
+
+    impl SoaProgram {
+        pub fn from_opcodes(opcodes: &[Opcode]) -> Self {
+            use Opcode::*;
+            let (kinds, operands) = opcodes
+                .iter()
+                .map(|opcode| match *opcode {
+                    BranchZero(target) => (BRANCH_ZERO, target as i32),
+                    BranchNotZero(target) => (BRANCH_NOT_ZERO, target as i32),
+                    AddRight(a, s) => (ADD_RIGHT, pack(a as u16, s as u16)),
+                    SetRight(a, s) => (SET_RIGHT, pack(a as u16, s as u16)),
+                    AddTo(o) => (ADD_TO, o as i32),
+                    SubTo(o) => (SUB_TO, o as i32),
+                    MoveTo(o) => (MOVE_TO, o as i32),
+                    Seek(s) => (SEEK, s as i32),
+                    Dot => (DOT, 0),
+                    Comma => (COMMA, 0),
+                    EmitConst(byte, count) => (EMIT_CONST, pack(byte as u16, count)),
+                    Copy(dst, temp) => (COPY, pack(dst as u16, temp as u16)),
+                    Block(count) => (BLOCK, count as i32),
+                    Exit => (EXIT, 0),
+                })
+                .unzip();
+            Self { kinds, operands }
+        }
+
+        pub fn len(&self) -> usize {
+            self.kinds.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.kinds.is_empty()
+        }
+
+        pub fn execute(
+            &self,
+            data: &mut [u8; DATA_LEN],
+            input: &mut impl Read,
+            output: &mut impl Write,
+            config: &RunConfig,
+        ) -> ExitStatus {
+            let mut pc: usize = 0;
+            let mut dp: usize = 0;
+            macro_rules! shift {
+                ($dp:expr, $i:expr) => {
+                    match shift_pointer($dp, $i as _, config) {
+                        Ok(dp) => dp,
+                        Err(status) => return status,
+                    }
+                };
+            }
+            while let (Some(&kind), Some(&operand)) = (self.kinds.get(pc), self.operands.get(pc)) {
+                match kind {
+                    BRANCH_ZERO => {
+                        if data[dp] == 0 {
+                            pc = operand as u32 as usize;
+                        }
+                    }
+                    BRANCH_NOT_ZERO => {
+                        if data[dp] != 0 {
+                            pc = operand as u32 as usize;
+                        }
+                    }
+                    ADD_RIGHT => {
+                        data[dp] = data[dp].wrapping_add(high(operand) as u8);
+                        dp = shift!(dp, operand as i16);
+                    }
+                    SET_RIGHT => {
+                        data[dp] = high(operand) as u8;
+                        dp = shift!(dp, operand as i16);
+                    }
+                    ADD_TO | SUB_TO if data[dp] == 0 => (),
+                    ADD_TO | SUB_TO | MOVE_TO => {
+                        let to = shift!(dp, operand);
+                        data[to] = if kind == SUB_TO {
+                            data[to].wrapping_sub(data[dp])
+                        } else {
+                            data[to].wrapping_add(data[dp])
+                        };
+                        data[dp] = 0;
+                        if kind == MOVE_TO {
+                            dp = to;
+                        }
+                    }
+                    SEEK => {
+                        for moved in 0.. {
+                            if data[dp] == 0 {
+                                break;
+                            }
+                            if moved == config.max_seek {
+                                return ExitStatus::SeekLimit;
+                            }
+                            dp = shift!(dp, operand);
+                        }
+                    }
+                    DOT => {
+                        let _ = output.write(&[data[dp]]);
+                    }
+                    COMMA => {
+                        let _ = output.flush();
+                        let Some(c) = read_byte(input) else {
+                            return ExitStatus::Halted;
+                        };
+                        data[dp] = c;
+                    }
+                    EMIT_CONST => {
+                        for _ in 0..operand as u16 {
+                            let _ = output.write(&[high(operand) as u8]);
+                        }
+                    }
+                    COPY => {
+                        let from = shift!(dp, operand as i16);
+                        let tmp = data[dp];
+                        // The first loop, and with it `dst`, is skipped on a zero.
+                        if tmp != 0 {
+                            let to = shift!(dp, high(operand) as i16);
+                            data[to] = data[to].wrapping_add(tmp);
+                        }
+                        data[dp] = tmp.wrapping_add(data[from]);
+                        data[from] = 0;
+                        dp = from;
+                    }
+                    // The opcodes it groups follow it and run as usual.
+                    BLOCK => (),
+                    _ => return ExitStatus::Halted,
+                }
+                pc += 1;
+            }
+            ExitStatus::Halted
+        }
+    }
+}
+
+pub use sandbox::{run_to_completion_with_limits, run_untrusted, Limits, RunOutcome, SandboxError};
+/// Running programs from people you don't trust.
+pub mod sandbox {
+    use super::{
+        check_brackets_nested, compile_from_str, new_tape, BfEngine, CompileError, ExitStatus,
+        Observer, RawEngine, RunConfig, RunStats, DATA_LEN, MAX_NESTING,
+    };
+    use std::time::{Duration, Instant};
+
+    #[derive(Copy, Clone, Debug)]
+    pub struct Limits {
+        /// Opcodes executed, one per source command.
+        pub max_steps: u64,
+        pub timeout: Duration,
+        /// Bytes of output.
+        pub max_output: usize,
+        /// Loops inside loops, checked before compiling.
+        pub max_nesting: usize,
+    }
+
+    impl Default for Limits {
+        fn default() -> Self {
+            Self {
+                max_steps: 100_000_000,
+                timeout: Duration::from_secs(1),
+                max_output: 1 << 16,
+                max_nesting: MAX_NESTING,
+            }
+        }
+    }
+
+    impl Limits {
+        /// No limit on anything, for programs you trust.
+        pub fn unlimited() -> Self {
+            Self {
+                max_steps: u64::MAX,
+                timeout: Duration::MAX,
+                max_output: usize::MAX,
+                max_nesting: usize::MAX,
+            }
+        }
+    }
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    pub enum SandboxError {
+        Compile(CompileError),
+        StepLimit,
+        Timeout,
+        OutputLimit,
+        /// Moved off either end of the `DATA_LEN` cell tape, which doesn't wrap.
+        PointerOutOfBounds {
+            dp: usize,
+            attempted: isize,
+        },
+    }
+
+    impl std::fmt::Display for SandboxError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                SandboxError::Compile(e) => write!(f, "{e}"),
+                SandboxError::StepLimit => f.write_str("step limit exceeded"),
+                SandboxError::Timeout => f.write_str("timed out"),
+                SandboxError::OutputLimit => f.write_str("output limit exceeded"),
+                SandboxError::PointerOutOfBounds { attempted, .. } => {
+                    write!(f, "pointer moved out of bounds to {attempted}")
+                }
+            }
+        }
+    }
+
+    impl std::error::Error for SandboxError {}
+
+    /// How often the clock is read, in steps.
+    const CLOCK_INTERVAL: u64 = 1 << 12;
+
+    struct Guard {
+        limits: Limits,
+        /// `None` for a timeout too long to be reached.
+        deadline: Option<Instant>,
+        steps: u64,
+        output: usize,
+        tripped: Option<SandboxError>,
+    }
+
+    impl Guard {
+        fn new(limits: Limits) -> Self {
+            Self {
+                limits,
+                deadline: Instant::now().checked_add(limits.timeout),
+                steps: 0,
+                output: 0,
+                tripped: None,
+            }
+        }
+    }
+
+    impl Observer for Guard {
+        fn output(&mut self, _byte: u8) {
+            self.output += 1;
+            if self.output > self.limits.max_output {
+                self.tripped = Some(SandboxError::OutputLimit);
+            }
+        }
+
+        fn pause(&mut self, _data: &[u8; DATA_LEN], _dp: usize) -> bool {
+            self.steps += 1;
+            if self.steps > self.limits.max_steps {
+                self.tripped = Some(SandboxError::StepLimit);
+            } else if self.steps.is_multiple_of(CLOCK_INTERVAL)
+                && self
+                    .deadline
+                    .is_some_and(|deadline| Instant::now() > deadline)
+            {
+                self.tripped = Some(SandboxError::Timeout);
+            }
+            self.tripped.is_some()
+        }
+    }
+
+    /// Runs `src` on `input` within `limits` and returns its output. Uses the
+    /// [`RawEngine`], which bounds-checks every access, on a tape that doesn't
+    /// wrap.
+    pub fn run_untrusted(src: &str, input: &[u8], limits: Limits) -> Result<Vec<u8>, SandboxError> {
+        check_brackets_nested(src.as_bytes(), limits.max_nesting).map_err(SandboxError::Compile)?;
+        let opcodes = compile_from_str::<RawEngine>(src).map_err(SandboxError::Compile)?;
+        let config = RunConfig::default().strict_tape(true);
+        let mut data = new_tape(&config);
+        let mut output = Vec::new();
+        let mut guard = Guard::new(limits);
+        let status = RawEngine::execute_observed(
+            &opcodes,
+            &mut data,
+            &mut &input[..],
+            &mut output,
+            &config,
+            &mut guard,
+        );
+        match status {
+            ExitStatus::Halted => Ok(output),
+            ExitStatus::Interrupted | ExitStatus::Quiescent => {
+                Err(guard.tripped.unwrap_or(SandboxError::Timeout))
+            }
+            ExitStatus::PointerOutOfBounds { dp, attempted } => {
+                Err(SandboxError::PointerOutOfBounds { dp, attempted })
+            }
+            // Raw has no `Seek`, but a scan that long is a step limit all the same.
+            ExitStatus::SeekLimit => Err(SandboxError::StepLimit),
+        }
+    }
+
+    /// Everything a run left behind, from [`run_to_completion_with_limits`].
+    #[derive(Clone, Debug)]
+    pub struct RunOutcome {
+        pub output: Vec<u8>,
+        pub tape: Box<[u8]>,
+        /// `Interrupted` if a limit stopped the run.
+        pub exit: ExitStatus,
+        pub stats: RunStats,
+        /// The limit that stopped the run, if any.
+        pub limit: Option<SandboxError>,
+    }
+
+    /// Compiles `src` for `E` and runs it on `input` within `limits`. Unlike
+    /// [`run_untrusted`], hitting a limit isn't an error: whatever the program
+    /// got done is still returned. `max_steps` counts `E`'s opcodes, not source
+    /// commands.
+    pub fn run_to_completion_with_limits<E: BfEngine>(
+        src: &str,
+        input: &[u8],
+        config: &RunConfig,
+        limits: Limits,
+    ) -> Result<RunOutcome, CompileError> {
+        check_brackets_nested(src.as_bytes(), limits.max_nesting)?;
+        let opcodes = compile_from_str::<E>(src)?;
+        Ok(run_with_limits::<E>(&opcodes, input, config, limits))
+    }
+
+    /// Runs already compiled `opcodes` on a fresh tape within `limits`.
+    pub(crate) fn run_with_limits<E: BfEngine>(
+        opcodes: &[E::OPCODE],
+        input: &[u8],
+        config: &RunConfig,
+        limits: Limits,
+    ) -> RunOutcome {
+        let mut data = new_tape(config);
+        let mut output = Vec::new();
+        let mut stats = RunStats::default();
+        let mut guard = Guard::new(limits);
+        let exit = E::execute_observed(
+            opcodes,
+            &mut data,
+            &mut &input[..],
+            &mut output,
+            config,
+            &mut (&mut stats, &mut guard),
+        );
+        RunOutcome {
+            output,
+            tape: data,
+            exit,
+            stats,
+            limit: guard.tripped,
+        }
+    }
+}
+
+pub use program::{compile_and_run_by_name, CompiledProgram, ENGINES};
+pub mod program {
+    use super::sandbox::run_with_limits;
+    use super::{
+        check_brackets, compile_from_str, run_to_completion_with_limits, BfEngine,
+        CheckedShiftAddEngine, CompileError, Limits, MergeTokenEngine, MergeTokenEngineExtra,
+        OptimizationLog, RawEngine, RunConfig, RunOutcome, ShiftAddEngine, SourceSpan,
+        SparseEngine,
+    };
+
+    type Runner = fn(&str, &[u8], &RunConfig, Limits) -> Result<RunOutcome, CompileError>;
+
+    /// Every engine by [`BfEngine::NAME`], fastest first.
+    pub const ENGINES: [(&str, Runner); 6] = [
+        (
+            ShiftAddEngine::NAME,
+            run_to_completion_with_limits::<ShiftAddEngine>,
+        ),
+        (
+            CheckedShiftAddEngine::NAME,
+            run_to_completion_with_limits::<CheckedShiftAddEngine>,
+        ),
+        (
+            SparseEngine::NAME,
+            run_to_completion_with_limits::<SparseEngine>,
+        ),
+        (
+            MergeTokenEngineExtra::NAME,
+            run_to_completion_with_limits::<MergeTokenEngineExtra>,
+        ),
+        (
+            MergeTokenEngine::NAME,
+            run_to_completion_with_limits::<MergeTokenEngine>,
+        ),
+        (RawEngine::NAME, run_to_completion_with_limits::<RawEngine>),
+    ];
+
+    /// Compiles `src` for the engine called `name`, ignoring case, and runs it
+    /// on `input` within `limits` like [`run_to_completion_with_limits`].
+    pub fn compile_and_run_by_name(
+        name: &str,
+        src: &str,
+        input: &[u8],
+        config: &RunConfig,
+        limits: Limits,
+    ) -> Result<RunOutcome, CompileError> {
+        let (_, run) = ENGINES
+            .iter()
+            .find(|(engine, _)| engine.eq_ignore_ascii_case(name))
+            .ok_or(CompileError {
+                message: "unknown engine",
+                offset: None,
+            })?;
+        run(src, input, config, limits)
+    }
+
+    /// A program compiled once for `E`, kept with its source so it can be run
+    /// any number of times.
+    pub struct CompiledProgram<E: BfEngine> {
+        source: String,
+        opcodes: Vec<E::OPCODE>,
+        /// What `generate` rewrote, from [`CompiledProgram::compile_annotated`].
+        pub log: Option<OptimizationLog>,
+        /// Where in the source each opcode came from, from
+        /// [`CompiledProgram::compile_annotated`].
+        pub spans: Option<Vec<SourceSpan>>,
+    }
+
+    impl<E: BfEngine> CompiledProgram<E> {
+        pub fn compile(src: &str) -> Result<Self, CompileError> {
+            Ok(Self {
+                source: src.to_string(),
+                opcodes: compile_from_str::<E>(src)?,
+                log: None,
+                spans: None,
+            })
+        }
+
+        pub fn source(&self) -> &str {
+            &self.source
+        }
+
+        pub fn opcodes(&self) -> &[E::OPCODE] {
+            &self.opcodes
+        }
+
+        /// Runs on a fresh tape within `limits`. Only the tape and output are
+        /// allocated, the opcodes are reused.
+        pub fn run(&self, input: &[u8], config: &RunConfig, limits: Limits) -> RunOutcome {
+            run_with_limits::<E>(&self.opcodes, input, config, limits)
+        }
+    }
+
+    impl CompiledProgram<ShiftAddEngine> {
+        /// `compile`, also filling in `log` and `spans`.
+        pub fn compile_annotated(src: &str) -> Result<Self, CompileError> {
+            check_brackets(src.as_bytes())?;
+            let (opcodes, log, spans) = ShiftAddEngine::generate_annotated(src.as_bytes())?;
+            Ok(Self {
+                source: src.to_string(),
+                opcodes,
+                log: Some(log),
+                spans: Some(spans),
+            })
+        }
+    }
+}
+
+pub use equivalence::{equivalent, Divergence};
+/// Checking that two programs, say a hand-optimized rewrite and the original,
+/// do the same thing.
+pub mod equivalence {
+    use super::{run_to_completion_with_limits, BfEngine, CompileError, Limits, RunConfig};
+
+    /// The first input two programs disagreed on, from [`equivalent`].
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    pub struct Divergence {
+        /// Index into `inputs`.
+        pub input: usize,
+        /// First output byte that differs, or where the shorter output ends.
+        /// `None` if the output agrees but the tape or exit status doesn't.
+        pub output_offset: Option<usize>,
+    }
+
+    impl std::fmt::Display for Divergence {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self.output_offset {
+                Some(offset) => write!(f, "input {}: output differs at byte {offset}", self.input),
+                None => write!(
+                    f,
+                    "input {}: same output, different tape or exit",
+                    self.input
+                ),
+            }
+        }
+    }
+
+    /// Runs `a` and `b` with `E` on each of `inputs` and compares their output,
+    /// final tape and exit status. Returns `None` if they agree on every input.
+    ///
+    /// A run that hits `limits` is compared as it stood, so two programs that
+    /// don't halt within `max_steps` will rarely count as equivalent.
+    pub fn equivalent<E: BfEngine>(
+        a: &str,
+        b: &str,
+        inputs: &[&[u8]],
+        limits: Limits,
+    ) -> Result<Option<Divergence>, CompileError> {
+        let config = RunConfig::default();
+        for (input, bytes) in inputs.iter().enumerate() {
+            let a = run_to_completion_with_limits::<E>(a, bytes, &config, limits)?;
+            let b = run_to_completion_with_limits::<E>(b, bytes, &config, limits)?;
+            let output_offset = a
+                .output
+                .iter()
+                .zip(&b.output)
+                .position(|(x, y)| x != y)
+                .or((a.output.len() != b.output.len()).then(|| a.output.len().min(b.output.len())));
+            if output_offset.is_some() || a.tape != b.tape || a.exit != b.exit {
+                return Ok(Some(Divergence {
+                    input,
+                    output_offset,
+                }));
+            }
+        }
+        Ok(None)
+    }
+}
+
+pub use asserts::run_with_asserts;
+/// `@assert cellN == V` comments, which let a program state what its tape should
+/// hold when it finishes.
+pub mod asserts {
+    use super::{compile_from_str, new_tape, BfEngine, ExitStatus, Read, RunConfig, Write};
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    pub struct Assertion {
+        /// 1-based source line, for reporting.
+        pub line: usize,
+        pub cell: usize,
+        pub expected: u8,
+    }
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    pub struct AssertResult {
+        pub assertion: Assertion,
+        pub actual: u8,
+    }
+
+    impl AssertResult {
+        pub fn passed(&self) -> bool {
+            self.actual == self.assertion.expected
+        }
+    }
+
+    /// Finds every `@assert` directive in `src`.
+    pub fn parse(src: &str) -> Result<Vec<Assertion>, String> {
+        let mut assertions = Vec::new();
+        for (i, line) in src.lines().enumerate() {
+            let Some((_, directive)) = line.split_once("@assert") else {
+                continue;
+            };
+            let error = || format!("line {}: expected `@assert cellN == V`", i + 1);
+            let (cell, expected) = directive.split_once("==").ok_or_else(error)?;
+            let cell = cell.trim().strip_prefix("cell").ok_or_else(error)?;
+            assertions.push(Assertion {
+                line: i + 1,
+                cell: cell.parse().map_err(|_| error())?,
+                expected: expected.trim().parse().map_err(|_| error())?,
+            });
+        }
+        Ok(assertions)
+    }
+
+    /// Runs `src` and checks its `@assert` directives against the final tape.
+    pub fn run_with_asserts<E: BfEngine>(
+        src: &str,
+        input: &mut impl Read,
+        output: &mut impl Write,
+        config: &RunConfig,
+    ) -> Result<(ExitStatus, Vec<AssertResult>), String> {
+        let assertions = parse(src)?;
+        let opcodes = compile_from_str::<E>(src).map_err(|e| e.to_string())?;
+        let mut data = new_tape(config);
+        let status = E::execute(&opcodes, &mut data, input, output, config);
+        assertions
+            .into_iter()
+            .map(|assertion| {
+                let actual = *data.get(assertion.cell).ok_or_else(|| {
+                    format!("line {}: no cell {}", assertion.line, assertion.cell)
+                })?;
+                Ok(AssertResult { assertion, actual })
+            })
+            .collect::<Result<_, _>>()
+            .map(|results| (status, results))
+    }
+}
+
+/// Several named programs in one file, for shipping a corpus or a library of
+/// utilities together.
+pub mod archive {
+    const MAGIC: &[u8; 8] = b"BFARCHV1";
+
+    /// Magic, then the entry count as a little-endian `u32`, then each entry's
+    /// name and source as a little-endian `u32` length followed by the bytes.
+    pub fn pack(programs: &[(String, &str)]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&(programs.len() as u32).to_le_bytes());
+        for (name, src) in programs {
+            for field in [name.as_bytes(), src.as_bytes()] {
+                bytes.extend_from_slice(&(field.len() as u32).to_le_bytes());
+                bytes.extend_from_slice(field);
+            }
+        }
+        bytes
+    }
+
+    /// The `(name, source)` entries of an archive from [`pack`], in order.
+    pub fn unpack(bytes: &[u8]) -> Result<Vec<(String, String)>, &'static str> {
+        let mut rest = bytes.strip_prefix(MAGIC).ok_or("not a program archive")?;
+        let count = take_u32(&mut rest)?;
+        let mut programs = Vec::new();
+        for _ in 0..count {
+            let mut field = || {
+                let len = take_u32(&mut rest)?;
+                String::from_utf8(take(&mut rest, len)?.to_vec())
+                    .map_err(|_| "archive entry is not UTF-8")
+            };
+            programs.push((field()?, field()?));
+        }
+        Ok(programs)
+    }
+
+    fn take<'a>(rest: &mut &'a [u8], len: usize) -> Result<&'a [u8], &'static str> {
+        if rest.len() < len {
+            return Err("program archive is truncated");
+        }
+        let (field, tail) = rest.split_at(len);
+        *rest = tail;
+        Ok(field)
+    }
+
+    fn take_u32(rest: &mut &[u8]) -> Result<usize, &'static str> {
+        Ok(u32::from_le_bytes(take(rest, 4)?.try_into().unwrap()) as usize)
+    }
+}
+
+/// The loop nesting of a program as a GraphViz `dot` tree, for `dot -Tsvg`.
+#[cfg(feature = "dot")]
+pub mod loop_graph {
+    use super::BasicOpcode;
+    use std::fmt::Write;
+
+    /// One node per loop, labelled with the command indices of its `[` and
+    /// `]`, under the loops it's nested in and a root for the whole program.
+    pub fn loop_tree_dot(opcodes: &[BasicOpcode]) -> Result<String, &'static str> {
+        let mut dot = String::from("digraph loops {\n    node [shape=box];\n");
+        let _ = writeln!(dot, "    program [label=\"{} commands\"];", opcodes.len());
+        let mut open_stack = Vec::new();
+        for (i, opcode) in opcodes.iter().enumerate() {
+            match opcode {
+                BasicOpcode::Open => {
+                    let _ = match open_stack.last() {
+                        Some(parent) => writeln!(dot, "    loop{parent} -> loop{i};"),
+                        None => writeln!(dot, "    program -> loop{i};"),
+                    };
+                    open_stack.push(i);
+                }
+                BasicOpcode::Close => {
+                    let open = open_stack.pop().ok_or("unbalanced brackets: extra ]")?;
+                    let _ = writeln!(dot, "    loop{open} [label=\"{open}..={i}\"];");
+                }
+                _ => (),
+            }
+        }
+        if !open_stack.is_empty() {
+            return Err("unbalanced brackets: extra [");
+        }
+        dot.push_str("}\n");
+        Ok(dot)
+    }
+}
+
+/// Charts for sharing, written as SVG by hand since the crate takes no
+/// charting dependency.
+#[cfg(feature = "plot")]
+pub mod plot {
+    use super::shift_add_engine::{Opcode, OpcodeStats};
+    use std::fmt::Write;
+
+    const BAR_HEIGHT: usize = 20;
+    const LABEL_WIDTH: usize = 110;
+    const CHART_WIDTH: usize = 400;
+
+    /// A horizontal bar chart of `stats`, one bar per opcode variant.
+    pub fn opcode_histogram_svg(stats: &OpcodeStats) -> String {
+        let max = stats.counts.iter().copied().max().unwrap_or(0).max(1);
+        let width = LABEL_WIDTH + CHART_WIDTH + 60;
+        let height = BAR_HEIGHT * Opcode::NAMES.len() + 10;
+        let mut svg = String::new();
+        let _ = writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" font-family="monospace" font-size="12">"#
+        );
+        for (i, (name, &count)) in Opcode::NAMES.iter().zip(&stats.counts).enumerate() {
+            let y = 5 + i * BAR_HEIGHT;
+            let bar = count * CHART_WIDTH / max;
+            let _ = writeln!(
+                svg,
+                r#"<text x="{}" y="{}" text-anchor="end">{name}</text>"#,
+                LABEL_WIDTH - 5,
+                y + 14
+            );
+            let _ = writeln!(
+                svg,
+                r##"<rect x="{LABEL_WIDTH}" y="{}" width="{bar}" height="{}" fill="#4878a8"/>"##,
+                y + 2,
+                BAR_HEIGHT - 4
+            );
+            let _ = writeln!(
+                svg,
+                r#"<text x="{}" y="{}">{count}</text>"#,
+                LABEL_WIDTH + bar + 5,
+                y + 14
+            );
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+/// Lowers [`ShiftAddEngine`] opcodes to textual LLVM IR for compiling ahead of
+/// time, e.g. `clang -O2 prog.ll -o prog`.
+///
+/// Every opcode gets its own basic block and loops become conditional branches
+/// on the cell value. The data pointer lives in an `alloca` that `mem2reg`
+/// turns into registers. The tape wraps around like the default config; strict
+/// tape isn't supported.
+///
+/// This is text rather than an engine built on inkwell: the crate takes no
+/// dependencies, so there's no in-process JIT or object file output, and
+/// compiling the IR is left to `clang` or `lli`. The IR uses opaque pointers,
+/// so LLVM 14 needs `-opaque-pointers`.
+#[cfg(feature = "llvm")]
+pub mod llvm_ir {
+    use super::shift_add_engine::Opcode;
+    use super::{RunConfig, DATA_LEN};
+    use std::fmt::Write;
+
+    struct Emitter {
+        ir: String,
+        next: usize,
+    }
+
+    impl Emitter {
+        /// A fresh SSA name.
+        fn name(&mut self) -> String {
+            self.next += 1;
+            format!("%t{}", self.next)
+        }
+
+        fn line(&mut self, line: &str) {
+            self.ir.push_str("  ");
+            self.ir.push_str(line);
+            self.ir.push('\n');
+        }
+
+        /// Pointer to the cell `offset` from the data pointer.
+        fn cell(&mut self, offset: i16) -> String {
+            let dp = self.name();
+            self.line(&format!("{dp} = load i64, ptr %dp"));
+            let sum = self.name();
+            self.line(&format!("{sum} = add i64 {dp}, {offset}"));
+            let index = self.name();
+            self.line(&format!("{index} = and i64 {sum}, {}", DATA_LEN - 1));
+            let ptr = self.name();
+            self.line(&format!(
+                "{ptr} = getelementptr inbounds [{DATA_LEN} x i8], ptr @tape, i64 0, i64 {index}"
+            ));
+            ptr
+        }
+
+        fn load(&mut self, ptr: &str) -> String {
+            let value = self.name();
+            self.line(&format!("{value} = load i8, ptr {ptr}"));
+            value
+        }
+
+        fn shift(&mut self, offset: i16) {
+            if offset == 0 {
+                return;
+            }
+            let dp = self.name();
+            self.line(&format!("{dp} = load i64, ptr %dp"));
+            let sum = self.name();
+            self.line(&format!("{sum} = add i64 {dp}, {offset}"));
+            let index = self.name();
+            self.line(&format!("{index} = and i64 {sum}, {}", DATA_LEN - 1));
+            self.line(&format!("store i64 {index}, ptr %dp"));
+        }
+
+        /// Branches to `nonzero` or `zero` on the current cell.
+        fn branch(&mut self, nonzero: &str, zero: &str) {
+            let ptr = self.cell(0);
+            let value = self.load(&ptr);
+            let is_zero = self.name();
+            self.line(&format!("{is_zero} = icmp eq i8 {value}, 0"));
+            self.line(&format!("br i1 {is_zero}, label %{zero}, label %{nonzero}"));
+        }
+
+        fn put(&mut self, value: &str) {
+            let wide = self.name();
+            self.line(&format!("{wide} = zext i8 {value} to i32"));
+            self.line(&format!("call i32 @putchar(i32 {wide})"));
+        }
+    }
+
+    /// The program as a module with a `main` that runs it on stdin and stdout.
+    pub fn emit(opcodes: &[Opcode], config: &RunConfig) -> String {
+        use Opcode::*;
+        let mut e = Emitter {
+            ir: String::new(),
+            next: 0,
+        };
+        let _ = writeln!(
+            e.ir,
+            "@tape = internal global [{DATA_LEN} x i8] zeroinitializer\n\n\
+             declare i32 @putchar(i32)\n\
+             declare i32 @getchar()\n\
+             declare i32 @fflush(ptr)\n\
+             declare void @llvm.memset.p0.i64(ptr, i8, i64, i1)\n\n\
+             define i32 @main() {{\n\
+             entry:\n  \
+             %dp = alloca i64\n  \
+             store i64 0, ptr %dp\n  \
+             call void @llvm.memset.p0.i64(ptr @tape, i8 {}, i64 {DATA_LEN}, i1 false)\n  \
+             br label %op0",
+            config.tape_fill
+        );
+        for (pc, &opcode) in opcodes.iter().enumerate() {
+            let next = format!("op{}", pc + 1);
+            let _ = writeln!(e.ir, "op{pc}:");
+            match opcode {
+                AddRight(a, s) | SetRight(a, s) => {
+                    let ptr = e.cell(0);
+                    let value = if let AddRight(..) = opcode {
+                        let old = e.load(&ptr);
+                        let value = e.name();
+                        e.line(&format!("{value} = add i8 {old}, {a}"));
+                        value
+                    } else {
+                        a.to_string()
+                    };
+                    e.line(&format!("store i8 {value}, ptr {ptr}"));
+                    e.shift(s);
+                }
+                AddTo(o) | SubTo(o) | MoveTo(o) => {
+                    let from = e.cell(0);
+                    let to = e.cell(o);
+                    let x = e.load(&from);
+                    let y = e.load(&to);
+                    let sum = e.name();
+                    let op = if let SubTo(_) = opcode { "sub" } else { "add" };
+                    e.line(&format!("{sum} = {op} i8 {y}, {x}"));
+                    e.line(&format!("store i8 {sum}, ptr {to}"));
+                    e.line(&format!("store i8 0, ptr {from}"));
+                    if let MoveTo(_) = opcode {
+                        e.shift(o);
+                    }
+                }
+                Copy(dst, temp) => {
+                    let here = e.cell(0);
+                    let to = e.cell(dst);
+                    let from = e.cell(temp);
+                    let x = e.load(&here);
+                    let y = e.load(&to);
+                    let sum = e.name();
+                    e.line(&format!("{sum} = add i8 {y}, {x}"));
+                    e.line(&format!("store i8 {sum}, ptr {to}"));
+                    let back = e.load(&from);
+                    let restored = e.name();
+                    e.line(&format!("{restored} = add i8 {x}, {back}"));
+                    e.line(&format!("store i8 {restored}, ptr {here}"));
+                    e.line(&format!("store i8 0, ptr {from}"));
+                    e.shift(temp);
+                }
+                Seek(s) => {
+                    let step = format!("seek{pc}");
+                    e.branch(&step, &next);
+                    let _ = writeln!(e.ir, "{step}:");
+                    e.shift(s);
+                    e.branch(&step, &next);
+                    continue;
+                }
+                Dot => {
+                    let ptr = e.cell(0);
+                    let value = e.load(&ptr);
+                    e.put(&value);
+                }
+                Comma => {
+                    e.line("call i32 @fflush(ptr null)");
+                    let c = e.name();
+                    e.line(&format!("{c} = call i32 @getchar()"));
+                    let eof = e.name();
+                    e.line(&format!("{eof} = icmp slt i32 {c}, 0"));
+                    let read = format!("read{pc}");
+                    e.line(&format!("br i1 {eof}, label %exit, label %{read}"));
+                    let _ = writeln!(e.ir, "{read}:");
+                    let byte = e.name();
+                    e.line(&format!("{byte} = trunc i32 {c} to i8"));
+                    let ptr = e.cell(0);
+                    e.line(&format!("store i8 {byte}, ptr {ptr}"));
+                }
+                EmitConst(byte, count) => {
+                    for _ in 0..count {
+                        e.line(&format!("call i32 @putchar(i32 {byte})"));
+                    }
+                }
+                BranchZero(close) => {
+                    e.branch(&next, &format!("op{}", close + 1));
+                    continue;
+                }
+                BranchNotZero(open) => {
+                    e.branch(&format!("op{}", open + 1), &next);
+                    continue;
+                }
+                // The opcodes it covers follow it anyway.
+                Block(_) => (),
+                Exit => {
+                    e.line("br label %exit");
+                    continue;
+                }
+            }
+            e.line(&format!("br label %{next}"));
+        }
+        let _ = writeln!(
+            e.ir,
+            "op{}:\n  br label %exit\nexit:\n  ret i32 0\n}}",
+            opcodes.len()
+        );
+        e.ir
+    }
+}
+
+pub use asm::assemble;
+/// Textual form of [`ShiftAddEngine`] opcodes, for running hand-written
+/// optimized code without going through `generate`.
+///
+/// One opcode per line, written the way `Debug` prints it (`AddRight(1, -2)`,
+/// `Dot`), so profiler output can be pasted back in. Branch targets are opcode
+/// indices. Anything after `;` is a comment.
+pub mod asm {
+    use super::shift_add_engine::{validate, Opcode};
+    use std::str::FromStr;
+
+    pub fn assemble(src: &str) -> Result<Vec<Opcode>, String> {
+        let mut opcodes = Vec::new();
+        for (line_number, line) in src.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let opcode =
+                parse_opcode(line).map_err(|e| format!("line {}: {e}", line_number + 1))?;
+            opcodes.push(opcode);
+        }
+        if opcodes.last() != Some(&Opcode::Exit) {
+            opcodes.push(Opcode::Exit);
+        }
+
+        // `execute` trusts the program, so reject anything that could jump out of it.
+        validate(&opcodes)?;
+        Ok(opcodes)
+    }
+
+    fn parse_opcode(line: &str) -> Result<Opcode, String> {
+        use Opcode::*;
+        let (name, args) = match line.split_once('(') {
+            Some((name, rest)) => (name.trim(), rest.strip_suffix(')').ok_or("missing `)`")?),
+            None => (line, ""),
+        };
+        let args: Vec<&str> = args
+            .split(',')
+            .map(str::trim)
+            .filter(|a| !a.is_empty())
+            .collect();
+
+        fn arg<T: FromStr>(args: &[&str], i: usize) -> Result<T, String> {
+            args[i]
+                .parse()
+                .map_err(|_| format!("invalid argument `{}`", args[i]))
+        }
+
+        Ok(match (name, args.len()) {
+            ("BranchZero", 1) => BranchZero(arg(&args, 0)?),
+            ("BranchNotZero", 1) => BranchNotZero(arg(&args, 0)?),
+            ("AddRight", 2) => AddRight(arg(&args, 0)?, arg(&args, 1)?),
+            ("SetRight", 2) => SetRight(arg(&args, 0)?, arg(&args, 1)?),
+            ("AddTo", 1) => AddTo(arg(&args, 0)?),
+            ("SubTo", 1) => SubTo(arg(&args, 0)?),
+            ("MoveTo", 1) => MoveTo(arg(&args, 0)?),
+            ("Seek", 1) => Seek(arg(&args, 0)?),
+            ("Dot", 0) => Dot,
+            ("Comma", 0) => Comma,
+            ("EmitConst", 2) => EmitConst(arg(&args, 0)?, arg(&args, 1)?),
+            ("Copy", 2) => Copy(arg(&args, 0)?, arg(&args, 1)?),
+            ("Block", 1) => Block(arg(&args, 0)?),
+            ("Exit", 0) => Exit,
+            (name, n) => return Err(format!("unknown opcode `{name}` with {n} arguments")),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::shift_add_engine::Opcode::{self, *};
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    #[test]
+    fn generated_programs_validate() {
+        for seed in 0..200 {
+            let program = random_program::generate(seed, 64);
+            for level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+                let (opcodes, _) =
+                    ShiftAddEngine::generate_at(program.iter().copied(), level).unwrap();
+                assert_eq!(validate(&opcodes), Ok(()), "seed {seed} at {level:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn validate_rejects_malformed() {
+        assert!(validate(&[]).is_err());
+        assert!(validate(&[Dot]).is_err());
+        assert!(validate(&[BranchZero(1_000_000), Exit]).is_err());
+        assert!(validate(&[BranchZero(1), Exit, BranchNotZero(0), Exit]).is_err());
+        assert!(validate(&[Block(5), Exit]).is_err());
+        assert!(validate(&[Block(2), Dot, BranchZero(3), BranchNotZero(2), Exit]).is_err());
+        assert_eq!(validate(&[BranchZero(1), BranchNotZero(0), Exit]), Ok(()));
+    }
+
+    #[test]
+    #[should_panic(expected = "malformed ShiftAddEngine program")]
+    fn execute_rejects_malformed() {
+        ShiftAddEngine::execute_fresh(
+            &[BranchZero(1_000_000)],
+            &mut std::io::empty(),
+            &mut std::io::sink(),
+            &RunConfig::default(),
+        );
+    }
+
+    #[test]
+    fn assemble_rejects_unpaired_branches() {
+        assert!(assemble("BranchZero(2)\nBranchZero(2)\nBranchNotZero(0)").is_err());
+        assert!(assemble("BranchNotZero(1)\nBranchZero(0)").is_err());
+        assert!(assemble("Block(1)\nDot").is_err());
+
+        let opcodes = compile_from_str::<ShiftAddEngine>("++[>+++<-]>[.-]").unwrap();
+        let text: String = opcodes
+            .iter()
+            .map(|opcode| format!("{opcode:?}\n"))
+            .collect();
+        assert_eq!(assemble(&text), Ok(opcodes));
+    }
+
+    #[test]
+    fn rewrites_keep_programs_valid() {
+        let config = RunConfig::default();
+        for seed in 0..200 {
+            let program = random_program::generate(seed, 64);
+            let mut opcodes = ShiftAddEngine::generate(program.iter().copied()).unwrap();
+            remove_dead_code(&mut opcodes, &config);
+            fold_constant_loops(&mut opcodes, &config);
+            let mut grouped = group_blocks(&opcodes);
+            assert_eq!(group_blocks(&grouped), grouped, "seed {seed}");
+            remove_unreachable(&mut grouped);
+            assert_eq!(validate(&grouped), Ok(()), "seed {seed}");
+        }
+    }
+
+    /// `src` compiled and folded for a fresh default tape, after checking it
+    /// still runs like Raw.
+    fn folded(src: &str, input: &[u8]) -> Vec<Opcode> {
+        let config = RunConfig::default();
+        let mut opcodes = compile_from_str::<ShiftAddEngine>(src).unwrap();
+        fold_constant_loops(&mut opcodes, &config);
+        let raw = run_limited::<RawEngine>(src, input, Limits::default());
+        let run =
+            sandbox::run_with_limits::<ShiftAddEngine>(&opcodes, input, &config, Limits::default());
+        assert_eq!((&run.output, run.exit), (&raw.output, raw.exit), "{src}");
+        assert!(run.tape == raw.tape, "{src}: tapes differ");
+        opcodes
+    }
+
+    #[test]
+    fn constant_loops_fold_to_stores() {
+        assert_eq!(
+            folded(">+++[-<+>]<", b""),
+            [AddRight(0, 1), SetRight(0, -1), SetRight(3, 0), Exit]
+        );
+        assert_eq!(
+            folded("++++++++[->++++++++<]", b""),
+            [SetRight(0, 1), SetRight(64, -1), Exit]
+        );
+        assert_eq!(
+            folded("+++++[->+++>---<<]>>,<", b"x"),
+            [
+                SetRight(0, 1),
+                SetRight(15, 1),
+                SetRight(241, 0),
+                Comma,
+                AddRight(0, -1),
+                Exit
+            ]
+        );
+        assert_eq!(
+            folded("++[>+<-]>[-<+>]-", b""),
+            [
+                SetRight(0, 1),
+                SetRight(0, -1),
+                SetRight(2, 1),
+                AddRight(255, 0),
+                Exit
+            ]
+        );
+        // Loops with a counter that depends on input, and any under
+        // `strict_tape`, stay loops.
+        let src = ",[>+<-]>[<+>-]<[->++<]";
+        let kept = folded(src, b"x");
+        assert_eq!(kept, compile_from_str::<ShiftAddEngine>(src).unwrap());
+        assert!(kept.iter().any(|opcode| matches!(opcode, BranchZero(_))));
+        let mut strict = compile_from_str::<ShiftAddEngine>(">+++[-<+>]<").unwrap();
+        assert_eq!(
+            fold_constant_loops(&mut strict, &RunConfig::default().strict_tape(true)),
+            0
+        );
+
+        // `generate` folds too, once the program has set the counter itself.
+        let src = ",[-]>[-]<+++[->+++<]>[-<++>]";
+        let opcodes = compile_from_str::<ShiftAddEngine>(src).unwrap();
+        assert!(
+            !opcodes
+                .iter()
+                .any(|opcode| matches!(opcode, BranchZero(_) | AddTo(_) | MoveTo(_))),
+            "{opcodes:?}"
+        );
+        agrees_with_raw::<ShiftAddEngine>(src, b"x", &RunConfig::default());
+
+        let config = RunConfig::default();
+        let mut compared = 0;
+        for seed in 0..300 {
+            let program = random_program::generate(seed, 40);
+            let src: String = program
+                .iter()
+                .map(|opcode| opcode.to_byte() as char)
+                .collect();
+            let raw = run_limited::<RawEngine>(
+                &src,
+                b"ab",
+                Limits {
+                    max_steps: 20_000,
+                    ..Limits::default()
+                },
+            );
+            if raw.limit.is_some() {
+                continue;
+            }
+            let mut opcodes = ShiftAddEngine::generate(program.into_iter()).unwrap();
+            fold_constant_loops(&mut opcodes, &config);
+            let run = sandbox::run_with_limits::<ShiftAddEngine>(
+                &opcodes,
+                b"ab",
+                &config,
+                Limits::default(),
+            );
+            assert_eq!(
+                (&run.output, run.exit),
+                (&raw.output, raw.exit),
+                "seed {seed}"
+            );
+            assert!(run.tape == raw.tape, "seed {seed}: tapes differ");
+            compared += 1;
+        }
+        assert!(compared > 100, "{compared}");
+    }
+
+    #[test]
+    fn known_cells_print_as_emit_const() {
+        let src = "++++++++[->++++++++<]>........";
+        let opcodes = folded(src, b"");
+        assert!(opcodes.contains(&EmitConst(64, 8)), "{opcodes:?}");
+        assert!(!opcodes.contains(&Dot), "{opcodes:?}");
+
+        // `generate` knows the cell once the program has cleared it.
+        let src = "[-]>[-]++++++++[-<++++++++>]<....>.";
+        let opcodes = compile_from_str::<ShiftAddEngine>(src).unwrap();
+        assert_eq!(
+            opcodes,
+            [
+                SetRight(0, 1),
+                SetRight(0, -1),
+                SetRight(64, 0),
+                EmitConst(64, 4),
+                AddRight(0, 1),
+                EmitConst(0, 1),
+                Exit
+            ]
+        );
+        agrees_with_raw::<ShiftAddEngine>(src, b"", &RunConfig::default());
+        // Not after input.
+        let src = "[-]++,.";
+        assert!(compile_from_str::<ShiftAddEngine>(src)
+            .unwrap()
+            .contains(&Dot));
+    }
+
+    #[test]
+    fn generate_with_passes_runs_user_passes_last() {
+        // Upper-cases constant output. `EmitConst` only exists once the
+        // built-in passes have run, so this also shows they came first.
+        fn shout(opcodes: &mut Vec<Opcode>) {
+            assert!(!opcodes.iter().any(|opcode| matches!(opcode, BranchZero(_))));
+            for opcode in opcodes {
+                if let EmitConst(byte, _) = opcode {
+                    *byte = byte.to_ascii_uppercase();
+                }
+            }
+        }
+        let src = "[-]>[-]<++++++++[->++++++++++++<]>+..";
+        let commands = src.bytes().filter_map(to_basic_opcode);
+        let opcodes = ShiftAddEngine::generate_with_passes(commands, &[shout]).unwrap();
+        assert_eq!(opcodes.iter().rev().nth(1), Some(&EmitConst(b'A', 2)));
+        let run = sandbox::run_with_limits::<ShiftAddEngine>(
+            &opcodes,
+            b"",
+            &RunConfig::default(),
+            Limits::default(),
+        );
+        assert_eq!((run.output, run.exit), (b"AA".to_vec(), ExitStatus::Halted));
+    }
+
+    #[test]
+    fn generate_with_passes_rejects_a_broken_pass() {
+        let program = random_program::generate(1, 64);
+        let drop_last = |opcodes: &mut Vec<Opcode>| {
+            opcodes.pop();
+        };
+        let result = ShiftAddEngine::generate_with_passes(program.into_iter(), &[drop_last]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn sparse_engine_uses_the_callers_tape() {
+        let config = RunConfig::default();
+        let opcodes = compile_from_str::<SparseEngine>(">>.<<<+++.").unwrap();
+        let mut data = new_tape(&config);
+        data[2] = b'a';
+        let mut output = Vec::new();
+        let status = SparseEngine::execute(
+            &opcodes,
+            &mut data,
+            &mut std::io::empty(),
+            &mut output,
+            &config,
+        );
+        assert_eq!(status, ExitStatus::Halted);
+        // Left of 0 is outside `data`, not `DATA_LEN - 1`.
+        assert_eq!(output, [b'a', 3]);
+        assert_eq!(data[2], b'a');
+        assert_eq!(data[DATA_LEN - 1], 0);
+    }
+
+    /// Runs `src` on `E` within `limits` with the default config.
+    fn run_limited<E: BfEngine>(src: &str, input: &[u8], limits: Limits) -> RunOutcome {
+        run_to_completion_with_limits::<E>(src, input, &RunConfig::default(), limits).unwrap()
+    }
+
+    fn limits_stop<E: BfEngine>() {
+        let steps = Limits {
+            max_steps: 10_000,
+            timeout: Duration::MAX,
+            ..Limits::default()
+        };
+        let outcome = run_limited::<E>("+[]", b"", steps);
+        assert_eq!(outcome.exit, ExitStatus::Interrupted, "{}", E::NAME);
+        assert_eq!(outcome.limit, Some(SandboxError::StepLimit), "{}", E::NAME);
+
+        let output = Limits {
+            max_output: 100,
+            ..Limits::default()
+        };
+        let outcome = run_limited::<E>("+[.]", b"", output);
+        assert_eq!(
+            outcome.limit,
+            Some(SandboxError::OutputLimit),
+            "{}",
+            E::NAME
+        );
+        assert_eq!(outcome.output.len(), 101, "{}", E::NAME);
+
+        let time = Limits {
+            max_steps: u64::MAX,
+            timeout: Duration::from_millis(50),
+            ..Limits::default()
+        };
+        let outcome = run_limited::<E>("+[>+<]", b"", time);
+        assert_eq!(outcome.limit, Some(SandboxError::Timeout), "{}", E::NAME);
+    }
+
+    #[test]
+    fn limits_stop_every_engine() {
+        limits_stop::<RawEngine>();
+        limits_stop::<MergeTokenEngine>();
+        limits_stop::<MergeTokenEngineExtra>();
+        limits_stop::<ShiftAddEngine>();
+        limits_stop::<CheckedShiftAddEngine>();
+        limits_stop::<SparseEngine>();
+    }
+
+    fn equivalent_on<E: BfEngine>() {
+        let inputs: [&[u8]; 3] = [b"", b"a", b"xyz"];
+        let limits = Limits {
+            max_steps: 100_000,
+            ..Limits::default()
+        };
+        let same = |a, b| equivalent::<E>(a, b, &inputs, limits).unwrap();
+        assert_eq!(same("+[-],[.,]", "++--[+],[.,]"), None, "{}", E::NAME);
+        let divergence = Divergence {
+            input: 1,
+            output_offset: Some(0),
+        };
+        assert_eq!(same(",[.,]", ",[+.,]"), Some(divergence), "{}", E::NAME);
+        let divergence = Divergence {
+            input: 0,
+            output_offset: None,
+        };
+        assert_eq!(same("+", "++"), Some(divergence), "{}", E::NAME);
+        // Neither halts: compared as they stood when the step limit hit.
+        assert_eq!(same("+[]", "+[]"), None, "{}", E::NAME);
+        assert_eq!(same("+[]", "-[]"), Some(divergence), "{}", E::NAME);
+    }
+
+    #[test]
+    fn equivalent_on_every_engine() {
+        equivalent_on::<RawEngine>();
+        equivalent_on::<MergeTokenEngine>();
+        equivalent_on::<MergeTokenEngineExtra>();
+        equivalent_on::<ShiftAddEngine>();
+        equivalent_on::<CheckedShiftAddEngine>();
+        equivalent_on::<SparseEngine>();
+    }
+
+    #[test]
+    fn programs_by_name_stop_at_limits() {
+        let config = RunConfig::default();
+        let limits = Limits {
+            max_steps: 10_000,
+            ..Limits::default()
+        };
+        for (name, _) in ENGINES {
+            let outcome = compile_and_run_by_name(name, "+[]", b"", &config, limits).unwrap();
+            assert_eq!(outcome.limit, Some(SandboxError::StepLimit), "{name}");
+        }
+        let program = CompiledProgram::<MergeTokenEngine>::compile("+[]").unwrap();
+        let outcome = program.run(b"", &config, limits);
+        assert_eq!(outcome.limit, Some(SandboxError::StepLimit));
+        let program = CompiledProgram::<MergeTokenEngine>::compile("+++[-.]").unwrap();
+        let outcome = program.run(b"", &config, Limits::unlimited());
+        assert_eq!(
+            (outcome.exit, outcome.output),
+            (ExitStatus::Halted, vec![2, 1, 0])
+        );
+    }
+
+    fn strict_walk<E: BfEngine>() {
+        let config = RunConfig::default().strict_tape(true);
+        let limits = Limits::unlimited();
+        let outcome = run_to_completion_with_limits::<E>("+[>+]", b"", &config, limits).unwrap();
+        let exit = ExitStatus::PointerOutOfBounds {
+            dp: DATA_LEN - 1,
+            attempted: DATA_LEN as isize,
+        };
+        assert_eq!(outcome.exit, exit, "{}", E::NAME);
+        assert!(outcome.tape.iter().all(|&cell| cell == 1), "{}", E::NAME);
+    }
+
+    #[test]
+    fn strict_tape_stops_past_the_end() {
+        strict_walk::<RawEngine>();
+        strict_walk::<MergeTokenEngine>();
+        strict_walk::<MergeTokenEngineExtra>();
+        strict_walk::<ShiftAddEngine>();
+        strict_walk::<CheckedShiftAddEngine>();
+    }
+
+    #[test]
+    fn strict_tape_checks_net_moves_when_merged() {
+        let config = RunConfig::default().strict_tape(true);
+        let run = |engine| {
+            let limits = Limits::unlimited();
+            compile_and_run_by_name(engine, "..<>-", b"", &config, limits)
+                .unwrap()
+                .exit
+        };
+        let exit = ExitStatus::PointerOutOfBounds {
+            dp: 0,
+            attempted: -1,
+        };
+        assert_eq!(run(RawEngine::NAME), exit);
+        for engine in [
+            MergeTokenEngine::NAME,
+            MergeTokenEngineExtra::NAME,
+            ShiftAddEngine::NAME,
+        ] {
+            assert_eq!(run(engine), ExitStatus::Halted, "{engine}");
+        }
+    }
+
+    #[test]
+    fn soa_copy_skips_dst_on_zero() {
+        let config = RunConfig::default().strict_tape(true);
+        // `Copy(-1, 1)` at cell 0, where `dst` is off the tape.
+        let opcodes = compile_from_str::<ShiftAddEngine>("[<+>>+<-]>[<+>-]<.").unwrap();
+        assert!(opcodes.contains(&Copy(-1, 1)), "{opcodes:?}");
+        for fill in [0, 1] {
+            let config = config.tape_fill(fill);
+            let mut soa_data = new_tape(&config);
+            let soa = SoaProgram::from_opcodes(&opcodes);
+            let soa_exit = soa.execute(
+                &mut soa_data,
+                &mut std::io::empty(),
+                &mut Vec::new(),
+                &config,
+            );
+            let (exit, data) = ShiftAddEngine::execute_fresh(
+                &opcodes,
+                &mut std::io::empty(),
+                &mut Vec::new(),
+                &config,
+            );
+            assert_eq!(soa_exit, exit, "fill {fill}");
+            assert_eq!(soa_data, data, "fill {fill}");
+        }
+    }
+
+    /// Runs `src` on `input` with `E` and with [`RawEngine`] and checks they
+    /// agree on output, tape and exit. Programs the step limit stops on Raw
+    /// aren't compared: one fused opcode can stand for thousands of Raw steps,
+    /// so `E` may well finish them.
+    fn agrees_with_raw<E: BfEngine>(src: &str, input: &[u8], config: &RunConfig) {
+        let limits = Limits {
+            max_steps: 1_000_000,
+            ..Limits::default()
+        };
+        let raw = run_to_completion_with_limits::<RawEngine>(src, input, config, limits).unwrap();
+        if raw.limit.is_some() {
+            return;
+        }
+        // Never more opcodes than Raw runs commands.
+        let other = run_to_completion_with_limits::<E>(src, input, config, limits).unwrap();
+        let context = format!("{} on {src:?} with input {input:?}", E::NAME);
+        assert_eq!(other.limit, None, "{context}: stopped, Raw didn't");
+        assert_eq!(other.output, raw.output, "{context}");
+        assert_eq!(other.exit, raw.exit, "{context}");
+        assert!(other.tape == raw.tape, "{context}: tapes differ");
+    }
+
+    #[test]
+    fn nested_clears_match_raw() {
+        for pattern in ["[[-]]", "[>[-]<]", "[->[-]+<]"] {
+            for setup in ["", "+++", "+++>+++<", ",>,<"] {
+                let src = format!("{setup}{pattern}.>.");
+                agrees_with_raw::<ShiftAddEngine>(&src, &[5, 7], &RunConfig::default());
+                agrees_with_raw::<MergeTokenEngineExtra>(&src, &[5, 7], &RunConfig::default());
+            }
+        }
+        let compiled = compile_from_str::<ShiftAddEngine>("[->[-]+<]").unwrap();
+        assert_eq!(
+            compiled,
+            [
+                BranchZero(3),
+                AddRight(255, 1),
+                SetRight(1, -1),
+                BranchNotZero(0),
+                Exit
+            ]
+        );
+    }
+
+    /// Small programs with an input each, covering every ShiftAdd opcode.
+    const CORPUS: &[(&str, &[u8])] = &[
+        (HELLO_WORLD, b""),
+        (",[.,]", b"cat\n"),
+        (">,[>,]<[.<]", b"reverse"),
+        (
+            ",>,[<+>-]<------------------------------------------------.",
+            b"34",
+        ),
+        ("++++[>+++++<-]>[<+++++>-]<.", b""),
+        ("+++[>+>+<<-]>>[<<+>>-]<<.>.", b""),
+        ("+++++[->-<]>.", b""),
+        ("+++++[->+<].>.", b""),
+        ("++++++++[>++++++++<-]>+....", b""),
+        (">>>+++[-<+<+>>]<<.<.", b""),
+        ("+>+>+>+>>+[<]<.", b""),
+        ("-.[-]<-.", b""),
+        ("[-]+++++..", b""),
+        (",----------[++++++++++.,----------]", b"line\nrest"),
+    ];
+
+    const HELLO_WORLD: &str = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.\
+        +++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+
+    #[test]
+    fn corpus_covers_every_opcode() {
+        let seen: std::collections::HashSet<&str> = (CORPUS.iter())
+            .flat_map(|(src, _)| compile_from_str::<ShiftAddEngine>(src).unwrap())
+            .map(|opcode| opcode.name())
+            .collect();
+        // `generate` never groups blocks.
+        for name in Opcode::NAMES.into_iter().filter(|&name| name != "Block") {
+            assert!(seen.contains(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn corpus_runs_checked() {
+        // Miri is slow enough that a few programs have to do.
+        let corpus = if cfg!(miri) { &CORPUS[..3] } else { CORPUS };
+        for &(src, input) in corpus {
+            agrees_with_raw::<CheckedShiftAddEngine>(src, input, &RunConfig::default());
+            agrees_with_raw::<ShiftAddEngine>(src, input, &RunConfig::default());
+        }
+    }
+
+    #[test]
+    fn seek_only_for_pure_moves() {
+        let compile = |src| compile_from_str::<ShiftAddEngine>(src).unwrap();
+        assert_eq!(compile("[>]"), [Seek(1), Exit]);
+        assert_eq!(compile("[<<]"), [Seek(-2), Exit]);
+        // A move with an add in the loop is a plain loop, never a `Seek`.
+        assert_eq!(
+            compile("[->]"),
+            [BranchZero(2), AddRight(255, 1), BranchNotZero(0), Exit]
+        );
+        assert_eq!(
+            compile("[>-]"),
+            [
+                BranchZero(3),
+                AddRight(0, 1),
+                AddRight(255, 0),
+                BranchNotZero(0),
+                Exit
+            ]
+        );
+        assert_eq!(
+            compile("[-<]"),
+            [BranchZero(2), AddRight(255, -1), BranchNotZero(0), Exit]
+        );
+        for src in ["[->]", "[>-]", "[-<]", "[>]"] {
+            for setup in ["+++>++>+<<", "+>>>+++<<<"] {
+                let src = format!("{setup}{src}.");
+                agrees_with_raw::<ShiftAddEngine>(&src, b"", &RunConfig::default());
+                agrees_with_raw::<MergeTokenEngineExtra>(&src, b"", &RunConfig::default());
+            }
+        }
+    }
+
+    #[test]
+    fn hello_world_under_every_config() {
+        let expected = b"Hello World!\n";
+        let program = HELLO_WORLD.bytes().filter_map(to_basic_opcode);
+        for strict_tape in [false, true] {
+            for max_seek in [DATA_LEN, 1 << 20] {
+                let config = RunConfig::default()
+                    .strict_tape(strict_tape)
+                    .max_seek(max_seek);
+                for input in [&b""[..], b"unread"] {
+                    for (name, _) in ENGINES {
+                        let limits = Limits::default();
+                        let outcome =
+                            compile_and_run_by_name(name, HELLO_WORLD, input, &config, limits);
+                        assert_eq!(outcome.unwrap().output, expected, "{name} with {config:?}");
+                    }
+                }
+                for level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+                    let (opcodes, _) = ShiftAddEngine::generate_at(program.clone(), level).unwrap();
+                    for opcodes in [group_blocks(&opcodes), opcodes] {
+                        let mut output = Vec::new();
+                        let input = &mut std::io::empty();
+                        let (exit, _) =
+                            ShiftAddEngine::execute_fresh(&opcodes, input, &mut output, &config);
+                        assert_eq!(exit, ExitStatus::Halted, "{level:?} with {config:?}");
+                        assert_eq!(output, expected, "{level:?} with {config:?}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn branches_past_u16_targets() {
+        let src = "+[.-]>".repeat(30_000);
+        let opcodes = compile_from_str::<ShiftAddEngine>(&src).unwrap();
+        assert!(opcodes.len() > 1 << 17, "{}", opcodes.len());
+        assert!(opcodes
+            .iter()
+            .any(|opcode| matches!(opcode, BranchZero(t) if *t > u16::MAX as u32)));
+        agrees_with_raw::<ShiftAddEngine>(&src, b"", &RunConfig::default());
+    }
+
+    #[test]
+    fn loop_spanning_past_u16() {
+        let src = format!("+[{}{}-]", ">+.".repeat(40_000), "<".repeat(40_000));
+        let opcodes = compile_from_str::<ShiftAddEngine>(&src).unwrap();
+        let (open, close) = opcodes
+            .iter()
+            .enumerate()
+            .find_map(|(pc, opcode)| match opcode {
+                BranchZero(close) => Some((pc, *close as usize)),
+                _ => None,
+            })
+            .unwrap();
+        assert!(close - open > u16::MAX as usize, "{open}..{close}");
+        assert!(matches!(opcodes[close], BranchNotZero(_)));
+        agrees_with_raw::<ShiftAddEngine>(&src, b"", &RunConfig::default());
+        agrees_with_raw::<CheckedShiftAddEngine>(&src, b"", &RunConfig::default());
+        agrees_with_raw::<MergeTokenEngine>(&src, b"", &RunConfig::default());
+        agrees_with_raw::<MergeTokenEngineExtra>(&src, b"", &RunConfig::default());
+        agrees_with_raw::<SparseEngine>(&src, b"", &RunConfig::default());
+    }
+
+    #[test]
+    fn cancelling_commands_compile_away() {
+        for src in ["+-", "-+", "<>", "><", "+-<>", "++--", "<<>>"] {
+            assert_eq!(
+                compile_from_str::<ShiftAddEngine>(src).unwrap(),
+                [Exit],
+                "{src}"
+            );
+            for level in [OptLevel::O1, OptLevel::O2] {
+                let commands = src.bytes().filter_map(to_basic_opcode);
+                let (opcodes, _) = ShiftAddEngine::generate_at(commands, level).unwrap();
+                assert_eq!(opcodes, [Exit], "{src} at {level:?}");
+            }
+            assert_eq!(
+                compile_from_str::<MergeTokenEngine>(src).unwrap(),
+                [],
+                "{src}"
+            );
+            assert_eq!(
+                compile_from_str::<MergeTokenEngineExtra>(src).unwrap(),
+                [],
+                "{src}"
+            );
+            // Nothing changes between the surrounding commands either.
+            let src = format!("+++>+{src}.<.");
+            agrees_with_raw::<ShiftAddEngine>(&src, b"", &RunConfig::default());
+            agrees_with_raw::<MergeTokenEngine>(&src, b"", &RunConfig::default());
+        }
+    }
+
+    #[test]
+    fn comma_is_never_merged() {
+        let compile = |src| compile_from_str::<ShiftAddEngine>(src).unwrap();
+        assert_eq!(compile(",-----"), [Comma, AddRight(251, 0), Exit]);
+        assert_eq!(
+            compile("+++,-----"),
+            [AddRight(3, 0), Comma, AddRight(251, 0), Exit]
+        );
+        assert_eq!(
+            compile("[-],+"),
+            [SetRight(0, 0), Comma, AddRight(1, 0), Exit]
+        );
+        for src in [",-----.", "+++,-----.", "[-],+.", ",,-.", ">,<,>."] {
+            for input in [&b"x"[..], b"xy", b""] {
+                agrees_with_raw::<ShiftAddEngine>(src, input, &RunConfig::default());
+            }
+        }
+        let outcome = run_limited::<ShiftAddEngine>(",-----.", b"x", Limits::default());
+        assert_eq!(outcome.output, [b'x' - 5]);
+    }
+
+    #[test]
+    fn add_lands_in_the_intended_cell() {
+        let compile = |src| compile_from_str::<ShiftAddEngine>(src).unwrap();
+        assert_eq!(compile(">+"), [AddRight(0, 1), AddRight(1, 0), Exit]);
+        assert_eq!(compile("+>"), [AddRight(1, 1), Exit]);
+        assert_eq!(compile("<-"), [AddRight(0, -1), AddRight(255, 0), Exit]);
+        assert_eq!(compile("-<"), [AddRight(255, -1), Exit]);
+        let config = RunConfig::default();
+        // (program, cell changed, its value) from cell 1 so `<` stays on the tape.
+        for (src, cell, value) in [(">+", 2, 1), ("+>", 1, 1), ("<-", 0, 255), ("-<", 1, 255)] {
+            let src = format!(">{src}");
+            let outcome = run_to_completion_with_limits::<ShiftAddEngine>(
+                &src,
+                b"",
+                &config,
+                Limits::default(),
+            );
+            let tape = outcome.unwrap().tape;
+            let changed: Vec<usize> = (0..DATA_LEN).filter(|&i| tape[i] != 0).collect();
+            assert_eq!(changed, [cell], "{src}");
+            assert_eq!(tape[cell], value, "{src}");
+            agrees_with_raw::<ShiftAddEngine>(&src, b"", &config);
+        }
+    }
+
+    /// The programs that found the `MergeTokenEngineExtra` bugs fixed when
+    /// fuzzing it against Raw.
+    #[test]
+    fn merge_extra_add_to_and_seek() {
+        let config = RunConfig::default();
+        // The `Seek` used to leave its loop's `BranchZero` behind.
+        agrees_with_raw::<MergeTokenEngineExtra>("+>+>+[<]>.", b"", &config);
+        agrees_with_raw::<MergeTokenEngineExtra>("+>>+[<<]>.<<<[>]+.", b"", &config);
+        // Negating the move overflowed on `i16::MIN`.
+        let far = format!("+[-{}+{}]", "<".repeat(32768), ">".repeat(32767));
+        agrees_with_raw::<MergeTokenEngineExtra>(&far, b"", &config);
+        // `AddTo` moved on a zero, which a strict tape noticed.
+        let strict = config.strict_tape(true);
+        agrees_with_raw::<MergeTokenEngineExtra>("[-<+>].", b"", &strict);
+        agrees_with_raw::<MergeTokenEngineExtra>(">+++[-<+>]<.", b"", &strict);
+        for src in [
+            "+++[->+<]>.",
+            "+++[->>+<<]>>.",
+            ">>++[-<<+>>]<<.",
+            "+++[>+<-]>.",
+        ] {
+            for config in [config, strict] {
+                agrees_with_raw::<MergeTokenEngineExtra>(src, b"", &config);
+            }
+        }
+        let compiled = compile_from_str::<MergeTokenEngineExtra>("[->>+<<]");
+        assert_eq!(
+            compiled.unwrap(),
+            [merge_token_engine_extra::Opcode::AddTo(2)]
+        );
+    }
+
+    #[test]
+    fn transfers_wrap_like_raw() {
+        let config = RunConfig::default();
+        // From cell 0 to the far end, and from the far end past it.
+        for src in [
+            "+++[-<+>]<.",
+            "+++[-<<<+>>>]<<<.",
+            "<+++[->>+<<]>>.<<.",
+            "<+++[->>-<<]>>.",
+            "<<+++[->>>+<<<]>>>.",
+            "+++[-<+>]>.",
+            "+++[-<+<+>>]<<[->>+<<]<.>>.",
+        ] {
+            let opcodes = compile_from_str::<ShiftAddEngine>(src).unwrap();
+            assert!(
+                opcodes
+                    .iter()
+                    .any(|o| matches!(o, AddTo(_) | SubTo(_) | MoveTo(_) | Copy(..))),
+                "{src} compiled to {opcodes:?}"
+            );
+            agrees_with_raw::<ShiftAddEngine>(src, b"", &config);
+            agrees_with_raw::<CheckedShiftAddEngine>(src, b"", &config);
+            agrees_with_raw::<MergeTokenEngineExtra>(src, b"", &config);
+        }
+    }
+
+    #[test]
+    fn transfer_direction_and_sign() {
+        let table = [
+            ("[->+<]", AddTo(1)),
+            ("[-<+>]", AddTo(-1)),
+            ("[->-<]", SubTo(1)),
+            ("[-<->]", SubTo(-1)),
+            ("[>+<-]", AddTo(1)),
+            ("[<+>-]", AddTo(-1)),
+            ("[>-<-]", SubTo(1)),
+            ("[<->-]", SubTo(-1)),
+            ("[->>>+<<<]", AddTo(3)),
+            ("[<<<->>>-]", SubTo(-3)),
+        ];
+        for (src, expected) in table {
+            let compiled = compile_from_str::<ShiftAddEngine>(src).unwrap();
+            assert_eq!(compiled, [expected, Exit], "{src}");
+            // Cells 1 to 7 hold 1, 2, 3, 5, 7, 11 and 13, starting on the 5.
+            let setup = [1, 2, 3, 5, 7, 11, 13].map(|n| ">".to_owned() + &"+".repeat(n));
+            let src = format!("{}<<<{src}", setup.concat());
+            agrees_with_raw::<ShiftAddEngine>(&src, b"", &RunConfig::default());
+            agrees_with_raw::<MergeTokenEngineExtra>(&src, b"", &RunConfig::default());
+        }
+    }
+
+    /// Stops after `limit` opcodes, keeping count in `steps`.
+    struct StepLimit {
+        steps: Rc<Cell<u64>>,
+        limit: u64,
+    }
+
+    impl Observer for StepLimit {
+        fn pause(&mut self, _data: &[u8; DATA_LEN], _dp: usize) -> bool {
+            self.steps.set(self.steps.get() + 1);
+            self.steps.get() >= self.limit
+        }
+    }
+
+    /// Output that notes how many opcodes had run when each byte arrived.
+    struct Arrivals {
+        steps: Rc<Cell<u64>>,
+        at: Vec<u64>,
+    }
+
+    impl Write for Arrivals {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.at.extend(buf.iter().map(|_| self.steps.get()));
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn streams<E: BfEngine>() {
+        let opcodes = compile_from_str::<E>("+[>+.<]").unwrap();
+        let steps = Rc::new(Cell::new(0));
+        let mut limit = StepLimit {
+            steps: steps.clone(),
+            limit: 100_000,
+        };
+        let mut output = Arrivals {
+            steps,
+            at: Vec::new(),
+        };
+        let mut data = new_tape(&RunConfig::default());
+        let input = &mut std::io::empty();
+        let config = RunConfig::default();
+        let exit =
+            E::execute_observed(&opcodes, &mut data, input, &mut output, &config, &mut limit);
+        assert_eq!(exit, ExitStatus::Interrupted, "{}", E::NAME);
+        // A byte every few opcodes from the start, not all at the end.
+        assert!(output.at.len() > 10_000, "{}", E::NAME);
+        assert!(output.at[0] < 10, "{}", E::NAME);
+        assert!(output.at.windows(2).all(|w| w[0] < w[1]), "{}", E::NAME);
+    }
+
+    #[test]
+    fn endless_generator_streams_under_a_step_limit() {
+        streams::<RawEngine>();
+        streams::<MergeTokenEngine>();
+        streams::<MergeTokenEngineExtra>();
+        streams::<ShiftAddEngine>();
+        streams::<CheckedShiftAddEngine>();
+        streams::<SparseEngine>();
+    }
+
+    /// Runs `src` on `E`, returning what it did if it halted within the step
+    /// limit.
+    fn halted<E: BfEngine>(src: &str, input: &[u8], config: &RunConfig) -> Option<RunOutcome> {
+        let limits = Limits {
+            max_steps: 20_000,
+            ..Limits::default()
+        };
+        let outcome = run_to_completion_with_limits::<E>(src, input, config, limits).unwrap();
+        outcome.limit.is_none().then_some(outcome)
+    }
+
+    /// Checks `E` against Raw on every program that halts on both. The sparse
+    /// tape doesn't wrap, so it's only held to programs that stay on the tape.
+    fn matrix_row<E: BfEngine>(programs: &[(String, Vec<u8>)], wraps: bool) -> usize {
+        let config = RunConfig::default();
+        let mut compared = 0;
+        for (src, input) in programs {
+            let strict = config.strict_tape(true);
+            if !wraps
+                && halted::<RawEngine>(src, input, &strict).map(|o| o.exit)
+                    != Some(ExitStatus::Halted)
+            {
+                continue;
+            }
+            let (Some(raw), Some(other)) = (
+                halted::<RawEngine>(src, input, &config),
+                halted::<E>(src, input, &config),
+            ) else {
+                continue;
+            };
+            let context = format!("{} on {src:?} with input {input:?}", E::NAME);
+            assert_eq!(other.output, raw.output, "{context}");
+            assert_eq!(other.exit, raw.exit, "{context}");
+            assert!(other.tape == raw.tape, "{context}: tapes differ");
+            compared += 1;
+        }
+        compared
+    }
+
+    #[test]
+    fn every_engine_matches_raw() {
+        let inputs: [&[u8]; 3] = [b"", b"abc", &[0, 255, 10, 1]];
+        let mut programs: Vec<(String, Vec<u8>)> = Vec::new();
+        for &(src, input) in CORPUS {
+            programs.push((src.to_owned(), input.to_vec()));
+            programs.extend(inputs.map(|input| (src.to_owned(), input.to_vec())));
+        }
+        for seed in 0..150 {
+            let src: String = (random_program::generate(seed, 40).into_iter())
+                .map(|opcode| opcode.to_byte() as char)
+                .collect();
+            programs.extend(inputs.map(|input| (src.clone(), input.to_vec())));
+        }
+        let compared = [
+            matrix_row::<MergeTokenEngine>(&programs, true),
+            matrix_row::<MergeTokenEngineExtra>(&programs, true),
+            matrix_row::<ShiftAddEngine>(&programs, true),
+            matrix_row::<CheckedShiftAddEngine>(&programs, true),
+            matrix_row::<SparseEngine>(&programs, false),
+        ];
+        // Plenty of the random programs halt, so the matrix isn't vacuous.
+        for count in compared {
+            assert!(count > programs.len() / 3, "{count} of {}", programs.len());
+        }
+    }
+
+    #[test]
+    fn loops_after_a_clear_compile_away() {
+        for (src, prefix) in [
+            ("[-][.+]", "[-]"),
+            ("+.[-][>,[.-]<]>.", "+.[-]>."),
+            ("[+][-[-]][>]", "[+]"),
+        ] {
+            let commands = src.bytes().filter_map(to_basic_opcode);
+            let (opcodes, log) = ShiftAddEngine::generate_at(commands, OptLevel::O2).unwrap();
+            assert!(log.dead_loops > 0, "{src}");
+            let commands = prefix.bytes().filter_map(to_basic_opcode);
+            let (expected, _) = ShiftAddEngine::generate_at(commands, OptLevel::O2).unwrap();
+            assert_eq!(opcodes, expected, "{src}");
+        }
+
+        let limits = Limits {
+            max_steps: 20_000,
+            ..Limits::default()
+        };
+        let config = RunConfig::default();
+        let mut dead_loops = 0;
+        let mut compared = 0;
+        for seed in 0..200 {
+            let before = random_program::generate(seed, 20);
+            let body = random_program::generate(seed + 1000, 20);
+            let program: Vec<BasicOpcode> = (before.iter().copied())
+                .chain("[-][".bytes().filter_map(to_basic_opcode))
+                .chain(body)
+                .chain("]".bytes().filter_map(to_basic_opcode))
+                .chain(before.iter().copied())
+                .collect();
+            let at = |level| ShiftAddEngine::generate_at(program.iter().copied(), level).unwrap();
+            let ((plain, _), (optimized, log)) = (at(OptLevel::O0), at(OptLevel::O2));
+            dead_loops += log.dead_loops;
+            for input in [&b""[..], b"abc"] {
+                let slow =
+                    sandbox::run_with_limits::<ShiftAddEngine>(&plain, input, &config, limits);
+                if slow.limit.is_some() {
+                    continue;
+                }
+                let fast =
+                    sandbox::run_with_limits::<ShiftAddEngine>(&optimized, input, &config, limits);
+                let context = format!("seed {seed} with input {input:?}");
+                assert_eq!(fast.limit, None, "{context}");
+                assert_eq!(fast.output, slow.output, "{context}");
+                assert_eq!(fast.exit, slow.exit, "{context}");
+                assert!(fast.tape == slow.tape, "{context}: tapes differ");
+                compared += 1;
+            }
+        }
+        assert!(dead_loops >= 200, "{dead_loops}");
+        assert!(compared > 100, "{compared}");
+    }
+
+    /// Runs the emitted IR for every corpus program under `lli`, when it's
+    /// installed, and compares the output with Raw.
+    #[cfg(feature = "llvm")]
+    #[test]
+    fn llvm_ir_matches_raw() {
+        use std::io::Write;
+        use std::process::{Command, Stdio};
+        let Ok(version) = Command::new("lli").arg("--version").output() else {
+            eprintln!("lli not found, skipping");
+            return;
+        };
+        // Opaque pointers are the default from LLVM 15 on.
+        let version = String::from_utf8_lossy(&version.stdout).into_owned();
+        let major = version
+            .split("version ")
+            .nth(1)
+            .and_then(|v| v.split('.').next()?.parse::<u32>().ok());
+        let flags: &[&str] = if major.is_some_and(|major| major < 15) {
+            &["-opaque-pointers"]
+        } else {
+            &[]
+        };
+        let config = RunConfig::default();
+        let path = std::env::temp_dir().join(format!("bf_interpreter_{}.ll", std::process::id()));
+        for &(src, input) in CORPUS {
+            let opcodes = compile_from_str::<ShiftAddEngine>(src).unwrap();
+            std::fs::write(&path, llvm_ir::emit(&opcodes, &config)).unwrap();
+            let mut lli = Command::new("lli")
+                .args(flags)
+                .arg(&path)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap();
+            lli.stdin.take().unwrap().write_all(input).unwrap();
+            let run = lli.wait_with_output().unwrap();
+            assert!(run.status.success(), "{src}");
+            let raw = run_limited::<RawEngine>(src, input, Limits::default());
+            assert_eq!(run.stdout, raw.output, "{src}");
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn run_parallel_matches_sequential_runs() {
+        let config = RunConfig::default();
+        // `[.>]` prints whatever an earlier run on the same tape left behind.
+        for src in [">,[>,]<[.<]", "[.>],."] {
+            let opcodes = compile_from_str::<ShiftAddEngine>(src).unwrap();
+            let inputs: Vec<Vec<u8>> = (0..100u8).map(|n| (1..=n % 7 + 1).collect()).collect();
+            let inputs: Vec<&[u8]> = inputs.iter().map(Vec::as_slice).collect();
+            let results = run_parallel::<ShiftAddEngine>(&opcodes, &inputs, &config);
+            assert_eq!(results.len(), inputs.len());
+            for (input, (status, output)) in inputs.iter().zip(results) {
+                let mut expected = Vec::new();
+                let (expected_status, _) = ShiftAddEngine::execute_fresh(
+                    &opcodes,
+                    &mut &input[..],
+                    &mut expected,
+                    &config,
+                );
+                assert_eq!(
+                    (status, output),
+                    (expected_status, expected),
+                    "{src} on {input:?}"
+                );
+            }
+        }
+        assert!(run_parallel::<ShiftAddEngine>(&[Exit], &[], &config).is_empty());
+    }
+
+    #[cfg(feature = "plot")]
+    #[test]
+    fn histogram_has_a_bar_per_opcode() {
+        let opcodes = compile_from_str::<ShiftAddEngine>(HELLO_WORLD).unwrap();
+        let stats = OpcodeStats::from_opcodes(&opcodes);
+        let svg = plot::opcode_histogram_svg(&stats);
+        assert!(svg.starts_with("<svg ") && svg.ends_with("</svg>\n"));
+        let widths: Vec<usize> = svg
+            .split(r#"<rect "#)
+            .skip(1)
+            .map(|rect| {
+                let width = rect.split(r#"width=""#).nth(1).unwrap();
+                width[..width.find('"').unwrap()].parse().unwrap()
+            })
+            .collect();
+        assert_eq!(widths.len(), Opcode::NAMES.len());
+        // The longest bar is the most common opcode, and bars scale with counts.
+        let max = *stats.counts.iter().max().unwrap();
+        for (&width, &count) in widths.iter().zip(&stats.counts) {
+            assert_eq!(width, count * 400 / max);
+            assert!(svg.contains(&format!(">{count}</text>")));
+        }
+        // Empty stats still draw, with no bars.
+        let svg = plot::opcode_histogram_svg(&OpcodeStats::default());
+        assert_eq!(svg.matches(r#"width="0""#).count(), Opcode::NAMES.len());
+    }
+
+    #[test]
+    fn clears_overwritten_before_a_read_go_away() {
+        let config = RunConfig::default();
+        // Set again before anything reads it: only the move is left.
+        let src = "[-]>>+[-<+>]<<[-]+";
+        assert_eq!(
+            compile_from_str::<ShiftAddEngine>(src).unwrap(),
+            [
+                AddRight(0, 2),
+                AddRight(1, 0),
+                AddTo(-1),
+                AddRight(0, -2),
+                SetRight(1, 0),
+                Exit
+            ]
+        );
+        agrees_with_raw::<ShiftAddEngine>(src, b"", &config);
+        // Read by `AddTo` before it's set, so it stays.
+        let src = ">[-]<[->+<]";
+        assert_eq!(
+            compile_from_str::<ShiftAddEngine>(src).unwrap(),
+            [AddRight(0, 1), SetRight(0, -1), AddTo(1), Exit]
+        );
+        for setup in ["", "+++>+++<"] {
+            agrees_with_raw::<ShiftAddEngine>(&format!("{setup}{src}>."), b"", &config);
+        }
+    }
+
+    #[test]
+    fn repeated_clears_collapse() {
+        for (src, expected) in [
+            ("[-][-]", &[SetRight(0, 0), Exit][..]),
+            ("[-]>[-]<[-]", &[SetRight(0, 1), SetRight(0, -1), Exit]),
+        ] {
+            assert_eq!(
+                compile_from_str::<ShiftAddEngine>(src).unwrap(),
+                expected,
+                "{src}"
+            );
+            agrees_with_raw::<ShiftAddEngine>(
+                &format!("+++>+++<{src}>."),
+                b"",
+                &RunConfig::default(),
+            );
+        }
+    }
+
+    #[test]
+    fn odd_step_loops_clear() {
+        assert_eq!(
+            compile_from_str::<ShiftAddEngine>("[+]").unwrap(),
+            [SetRight(0, 0), Exit]
+        );
+        // Even steps never reach zero from an odd value.
+        assert_eq!(
+            compile_from_str::<ShiftAddEngine>("[++]").unwrap(),
+            [BranchZero(2), AddRight(2, 0), BranchNotZero(0), Exit]
+        );
+        for setup in ["", "+", "++", "+++++"] {
+            let src = format!("{setup}[+]>+.");
+            agrees_with_raw::<ShiftAddEngine>(&src, b"", &RunConfig::default());
+            let src = format!("{setup}[++]>+.");
+            agrees_with_raw::<ShiftAddEngine>(&src, b"", &RunConfig::default());
+        }
+    }
+
+    /// The exit of `src` on `E`, starting on a tape of ones.
+    fn seek_exit<E: BfEngine>(src: &str, max_seek: usize) -> ExitStatus {
+        let config = RunConfig::default().tape_fill(1).max_seek(max_seek);
+        run_to_completion_with_limits::<E>(src, b"", &config, Limits::default())
+            .unwrap()
+            .exit
+    }
+
+    #[test]
+    fn seek_stops_on_a_tape_without_zeros() {
+        fn check<E: BfEngine>() {
+            assert_eq!(
+                seek_exit::<E>("[>]", DATA_LEN),
+                ExitStatus::SeekLimit,
+                "{}",
+                E::NAME
+            );
+            assert_eq!(
+                seek_exit::<E>("[<<]", 100),
+                ExitStatus::SeekLimit,
+                "{}",
+                E::NAME
+            );
+            // A zero five cells away is found within 5 moves, not within 4.
+            let src = ">>>>>[-]<<<<<[>]";
+            assert_eq!(seek_exit::<E>(src, 5), ExitStatus::Halted, "{}", E::NAME);
+            assert_eq!(seek_exit::<E>(src, 4), ExitStatus::SeekLimit, "{}", E::NAME);
+        }
+        check::<ShiftAddEngine>();
+        check::<CheckedShiftAddEngine>();
+        check::<MergeTokenEngineExtra>();
+        check::<SparseEngine>();
+    }
+
+    #[test]
+    fn long_moves_split_past_i16() {
+        let src = ">".repeat(40_000);
+        assert_eq!(
+            compile_from_str::<ShiftAddEngine>(&src).unwrap(),
+            [AddRight(0, 32767), AddRight(0, 7233), Exit]
+        );
+        for count in [40_000, 80_000] {
+            let src = format!("{}+", ">".repeat(count));
+            let run = run_limited::<ShiftAddEngine>(&src, b"", Limits::default());
+            let landed = run.tape.iter().position(|&cell| cell == 1);
+            assert_eq!(landed, Some(count % DATA_LEN), "{count}");
+            agrees_with_raw::<ShiftAddEngine>(&src, b"", &RunConfig::default());
+            agrees_with_raw::<ShiftAddEngine>(&src.replace('>', "<"), b"", &RunConfig::default());
+        }
+    }
+
+    #[test]
+    fn far_transfers() {
+        let config = RunConfig::default();
+        let (right, left) = (">".repeat(1000), "<".repeat(1000));
+        for (src, expected) in [
+            (format!("[-{right}+{left}]"), AddTo(1000)),
+            (format!("[{left}+{right}-]"), AddTo(-1000)),
+            (format!("[-{right}-{left}]"), SubTo(1000)),
+        ] {
+            assert_eq!(
+                compile_from_str::<ShiftAddEngine>(&src).unwrap(),
+                [expected, Exit]
+            );
+            agrees_with_raw::<ShiftAddEngine>(&format!(",{src}"), b"\x07", &config);
+        }
+        // Too far for an `i16` offset, so it stays a loop.
+        let (right, left) = (">".repeat(40_000), "<".repeat(40_000));
+        let src = format!("[-{right}+{left}]");
+        let opcodes = compile_from_str::<ShiftAddEngine>(&src).unwrap();
+        assert!(
+            matches!(opcodes[..], [BranchZero(_), .., BranchNotZero(0), Exit]),
+            "{opcodes:?}"
+        );
+        assert!(!opcodes.iter().any(|opcode| matches!(opcode, AddTo(_))));
+        agrees_with_raw::<ShiftAddEngine>(&format!(",{src}"), b"\x07", &config);
+    }
+
+    #[test]
+    fn copy_macro_golden() {
+        let src = ">[-]>[-]<<[->+>+<<]>>[-<<+>>]";
+        assert_eq!(
+            compile_from_str::<ShiftAddEngine>(src).unwrap(),
+            [
+                AddRight(0, 1),
+                SetRight(0, 1),
+                SetRight(0, -2),
+                Copy(1, 2),
+                Exit
+            ]
+        );
+        for input in [&b""[..], b"\0", b"a", b"\xff"] {
+            agrees_with_raw::<ShiftAddEngine>(
+                &format!(">+++>+++<<,{src}<<.>.>."),
+                input,
+                &RunConfig::default(),
+            );
+        }
+    }
+
+    /// Runs `src` in Extended Type I on a fresh tape of `C` cells.
+    fn run_cells<C: super::Cell>(src: &str, input: &[u8]) -> (Vec<u8>, Box<[C; DATA_LEN]>) {
+        let commands = src.bytes().filter_map(ExtendedTypeI::command);
+        let opcodes = RawEngine::generate(commands).unwrap();
+        let mut data: Box<[C; DATA_LEN]> = Box::new([C::from_byte(0); DATA_LEN]);
+        let mut output = Vec::new();
+        let config = RunConfig::default();
+        let exit = RawEngine::execute_cells(
+            &opcodes,
+            &mut data,
+            &mut &input[..],
+            &mut output,
+            &config,
+            &mut (),
+        );
+        assert_eq!(exit, ExitStatus::Halted, "{src}");
+        (output, data)
+    }
+
+    #[test]
+    fn signed_cells() {
+        for &(src, input) in CORPUS {
+            let (unsigned, unsigned_tape) = run_cells::<u8>(src, input);
+            let (signed, signed_tape) = run_cells::<i8>(src, input);
+            assert_eq!(signed, unsigned, "{src}");
+            assert!(signed_tape
+                .iter()
+                .zip(unsigned_tape.iter())
+                .all(|(&s, &u)| s as u8 == u));
+            let raw = run_limited::<RawEngine>(src, input, Limits::default());
+            assert_eq!(unsigned, raw.output, "{src}");
+        }
+        // -1 read as a byte, and halved keeping its sign.
+        let (output, tape) = run_cells::<i8>("-.>-}.>-{.", b"");
+        assert_eq!(
+            (output, tape[0], tape[1], tape[2]),
+            (vec![255, 255, 254], -1, -1, -2)
+        );
+        let (output, tape) = run_cells::<u8>("-.>-}.>-{.", b"");
+        assert_eq!(
+            (output, tape[0], tape[1], tape[2]),
+            (vec![255, 127, 254], 255, 127, 254)
+        );
+        // Counting up from -128 still takes 128 steps to reach zero.
+        let src = "++++++++[>++++++++++++++++<-]>[+>+<]>.";
+        let (output, tape) = run_cells::<i8>(src, b"");
+        assert_eq!((output, tape[1], tape[2]), (vec![128], 0, -128));
+    }
+}
@@ -1,691 +1,1420 @@
+use bf_interpreter::*;
 use std::io::Read;
 use std::io::Write;
 use std::time::Duration;
 use std::time::Instant;
 
-#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
-enum BasicOpcode {
-    Add,
-    Sub,
-    Open,
-    Close,
-    Right,
-    Left,
-    Dot,
-    Comma,
+/// Where the time went in one run. Printed, like the profile and stats, only
+/// once the run stops, which for a program that never halts means Ctrl-C with
+/// the `signal` feature.
+#[derive(Debug)]
+struct RunReport {
+    name: &'static str,
+    /// Reading the file and picking out the commands.
+    parse: Duration,
+    /// `generate` and any optimization passes after it.
+    compile: Duration,
+    allocate: Duration,
+    execute: Duration,
+    exit_status: ExitStatus,
 }
 
-const DATA_LEN: usize = 1 << 16;
+impl RunReport {
+    fn total(&self) -> Duration {
+        self.parse + self.compile + self.allocate + self.execute
+    }
+}
 
-fn to_basic_opcode(c: u8) -> Option<BasicOpcode> {
-    match c {
-        b'+' => Some(BasicOpcode::Add),
-        b'-' => Some(BasicOpcode::Sub),
-        b'>' => Some(BasicOpcode::Right),
-        b'<' => Some(BasicOpcode::Left),
-        b'[' => Some(BasicOpcode::Open),
-        b']' => Some(BasicOpcode::Close),
-        b'.' => Some(BasicOpcode::Dot),
-        b',' => Some(BasicOpcode::Comma),
-        _ => None,
+impl std::fmt::Display for RunReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "engine     {}", self.name)?;
+        for (phase, duration) in [
+            ("parse", self.parse),
+            ("compile", self.compile),
+            ("allocate", self.allocate),
+            ("execute", self.execute),
+            ("total", self.total()),
+        ] {
+            writeln!(f, "{phase:<10} {duration:>12.3?}")?;
+        }
+        writeln!(f, "exit       {:?}", self.exit_status)
     }
 }
 
-trait BfEngine {
-    const NAME: &'static str;
-    type OPCODE: std::fmt::Debug;
-    fn generate(iter: impl Iterator<Item = BasicOpcode>)
-        -> Result<Vec<Self::OPCODE>, &'static str>;
-    fn execute(
-        opcodes: &[Self::OPCODE],
-        data: &mut [u8; DATA_LEN],
-        input: &[u8],
-        stdout: &mut std::io::StdoutLock<'static>,
-    );
+/// The tape a run uses: boxed, or a file mapped with `--tape-file` so it can be
+/// inspected from outside while the program runs and after.
+enum Tape {
+    Boxed(Box<[u8; DATA_LEN]>),
+    #[cfg(all(feature = "mmap", unix))]
+    Mapped(mapped::TapeFile),
 }
 
-#[derive(Debug)]
-struct TimingReport {
-    name: &'static str,
-    compile_duration: Duration,
-    execute_duration: Duration,
+impl Tape {
+    fn new(config: &RunConfig, file: Option<&str>) -> Result<Self, &'static str> {
+        match file {
+            None => Ok(Tape::Boxed(new_tape(config))),
+            #[cfg(all(feature = "mmap", unix))]
+            Some(path) => {
+                let mut tape = Tape::Mapped(mapped::TapeFile::create(path)?);
+                tape.fill(config.tape_fill);
+                Ok(tape)
+            }
+            #[cfg(not(all(feature = "mmap", unix)))]
+            Some(_) => Err("--tape-file needs the mmap feature"),
+        }
+    }
+}
+
+impl std::ops::Deref for Tape {
+    type Target = [u8; DATA_LEN];
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Tape::Boxed(data) => data,
+            #[cfg(all(feature = "mmap", unix))]
+            Tape::Mapped(file) => file.tape(),
+        }
+    }
+}
+
+impl std::ops::DerefMut for Tape {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            Tape::Boxed(data) => data,
+            #[cfg(all(feature = "mmap", unix))]
+            Tape::Mapped(file) => file.tape_mut(),
+        }
+    }
 }
 
+/// Compiles and runs a program, timing each phase. The report's `parse` time is
+/// left for the caller, which did the parsing.
 fn compile_execute<ENGINE: BfEngine>(
-    iter: impl Iterator<Item = BasicOpcode>,
-    input: &[u8],
-) -> TimingReport {
+    generate: impl FnOnce() -> Result<Vec<ENGINE::OPCODE>, String>,
+    input: &mut impl Read,
+    output: &mut impl Write,
+    config: &RunConfig,
+    tape_file: Option<&str>,
+    observer: &mut impl Observer,
+) -> Result<(RunReport, Vec<ENGINE::OPCODE>, Tape), String> {
     let start = Instant::now();
-    println!("Generating...");
-    let opcodes = ENGINE::generate(iter).unwrap();
-    let compile_duration = start.elapsed();
-    println!(
-        "Generating opcodes for engine \"{}\" took {:?}",
-        ENGINE::NAME,
-        compile_duration
-    );
+    let opcodes = generate()?;
+    let compile = start.elapsed();
 
     let start = Instant::now();
-    println!("Allocating...");
-    let mut data: Box<[u8; DATA_LEN]> = (0..DATA_LEN)
-        .map(|_| 0_u8)
-        .collect::<Vec<_>>()
-        .into_boxed_slice()
-        .try_into()
-        .unwrap();
-    println!("Allocating {} bytes took {:?}", DATA_LEN, start.elapsed());
-
-    let stdout = &mut std::io::stdout().lock();
+    let mut data = Tape::new(config, tape_file)?;
+    let allocate = start.elapsed();
 
     let start = Instant::now();
-    println!("Executing...");
-    ENGINE::execute(&opcodes, &mut data, input, stdout);
-    let execute_duration = start.elapsed();
-    println!(
-        "Executing with engine \"{}\" took {:?}",
-        ENGINE::NAME,
-        execute_duration,
-    );
-    TimingReport {
-        compile_duration,
-        execute_duration,
+    let exit_status =
+        ENGINE::execute_observed(&opcodes, &mut data, input, output, config, observer);
+    let execute = start.elapsed();
+
+    let report = RunReport {
         name: ENGINE::NAME,
-    }
+        parse: Duration::ZERO,
+        compile,
+        allocate,
+        execute,
+        exit_status,
+    };
+    Ok((report, opcodes, data))
 }
 
 fn main() -> Result<(), &'static str> {
+    let mut config = RunConfig::default();
+    let mut hex_output = false;
+    let mut quiet = false;
+    let mut output_tail = None;
+    let mut output_buffering = "line".to_string();
+    let mut strip_control = Vec::new();
+    let mut trailing_newline = false;
+    let mut stats = None;
+    let mut warn_wrap = None;
+    let mut quiescent = None;
+    let mut checkpoint = None;
+    let mut checkpoint_every = 1 << 30;
+    let mut restore = None;
+    let mut profile = None;
+    let mut asm = false;
+    let mut blocks = false;
+    let mut strip_unreachable = false;
+    let mut opt_log = false;
+    let mut opt_level = OptLevel::O2;
+    let mut echo_input = false;
+    let mut extended = false;
+    let mut tape_file = None;
+    let mut archive = None;
+    let mut warn_unoptimized_loops = false;
+    let mut progress = false;
+    let mut mem_stats = false;
+    let mut show_profile = false;
+    let mut indent = Indent::default();
+    let mut save_profile = None;
+    let mut echo_source = false;
+    let mut dump_optimizations = false;
+    let mut lint = false;
+    let mut checked = false;
+    let mut engine = None;
+    // Only `--engine` runs go through the sandbox limits.
+    let mut limits = Limits::unlimited();
+    let mut limited = false;
+    let mut bench_runs = None;
+    #[cfg(feature = "plot")]
+    let mut plot_opcodes = None;
+    #[cfg(feature = "dot")]
+    let mut loop_dot = None;
+    #[cfg(feature = "llvm")]
+    let mut emit_llvm = None;
+    let mut compare_profile = None;
+    let mut gen_seed = None;
+    let mut gen_len = 100;
+    let mut verify_against = None;
+    let mut positional = Vec::new();
     let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--strict-tape" => config = config.strict_tape(true),
+            "--tape-fill" => {
+                let fill = args.next().ok_or("--tape-fill needs a byte value")?;
+                config = config.tape_fill(fill.parse().map_err(|_| "invalid --tape-fill value")?);
+            }
+            "--max-seek" => {
+                let cells = args.next().ok_or("--max-seek needs a cell count")?;
+                config = config.max_seek(cells.parse().map_err(|_| "invalid --max-seek count")?);
+            }
+            "--quiet" => quiet = true,
+            "--hex-output" => hex_output = true,
+            "--tail" => {
+                let len = args.next().ok_or("--tail needs a byte count")?;
+                output_tail = Some(len.parse().map_err(|_| "invalid --tail byte count")?);
+            }
+            "--output-buffering" => {
+                output_buffering = args
+                    .next()
+                    .ok_or("--output-buffering needs line, block or none")?;
+            }
+            "--strip-control" => {
+                let codes = args
+                    .next()
+                    .ok_or("--strip-control needs a list of byte values")?;
+                for code in codes.split(',') {
+                    strip_control.push(code.parse().map_err(|_| "invalid --strip-control value")?);
+                }
+            }
+            "--trailing-newline" => trailing_newline = true,
+            "--stats" => stats = Some(RunStats::default()),
+            "--warn-wrap" => warn_wrap = Some(WrapWarnings::new(20)),
+            "--checkpoint" => {
+                checkpoint = Some(args.next().ok_or("--checkpoint needs a file")?);
+            }
+            "--checkpoint-every" => {
+                let every = args
+                    .next()
+                    .ok_or("--checkpoint-every needs an opcode count")?;
+                checkpoint_every = every
+                    .parse()
+                    .map_err(|_| "invalid --checkpoint-every value")?;
+            }
+            "--restore" => restore = Some(args.next().ok_or("--restore needs a file")?),
+            "--quiescent" => {
+                let limit = args.next().ok_or("--quiescent needs an opcode count")?;
+                let limit = limit.parse().map_err(|_| "invalid --quiescent value")?;
+                quiescent = Some(Quiescence::new(limit));
+            }
+            "--indent" => {
+                let width = args.next().ok_or("--indent needs a width or \"tab\"")?;
+                indent = match width.as_str() {
+                    "tab" => Indent::Tab,
+                    width => Indent::Spaces(width.parse().map_err(|_| "invalid --indent width")?),
+                };
+            }
+            "--profile" => {
+                show_profile = true;
+                profile.get_or_insert_with(Profile::default);
+            }
+            "--asm" => asm = true,
+            "--blocks" => blocks = true,
+            "--strip-unreachable" => strip_unreachable = true,
+            "--opt-log" => opt_log = true,
+            "-O0" => opt_level = OptLevel::O0,
+            "-O1" => opt_level = OptLevel::O1,
+            "-O2" => opt_level = OptLevel::O2,
+            "--echo-input" => echo_input = true,
+            "--extended" => extended = true,
+            "--tape-file" => tape_file = Some(args.next().ok_or("--tape-file needs a file")?),
+            "--warn-unoptimized-loops" => warn_unoptimized_loops = true,
+            "--archive" => archive = Some(args.next().ok_or("--archive needs pack or run")?),
+            "--progress" => progress = true,
+            "--mem-stats" => mem_stats = true,
+            "--echo-source" => echo_source = true,
+            "--dump-optimizations" => dump_optimizations = true,
+            "--lint" => lint = true,
+            "--checked" => checked = true,
+            "--engine" => engine = Some(args.next().ok_or("--engine needs an engine name")?),
+            "--max-steps" => {
+                let steps = args.next().ok_or("--max-steps needs an opcode count")?;
+                limits.max_steps = steps.parse().map_err(|_| "invalid --max-steps count")?;
+                limited = true;
+            }
+            "--timeout" => {
+                let secs = args.next().ok_or("--timeout needs a number of seconds")?;
+                let secs = secs.parse().map_err(|_| "invalid --timeout value")?;
+                limits.timeout =
+                    Duration::try_from_secs_f64(secs).map_err(|_| "invalid --timeout value")?;
+                limited = true;
+            }
+            "--max-output" => {
+                let bytes = args.next().ok_or("--max-output needs a byte count")?;
+                limits.max_output = bytes.parse().map_err(|_| "invalid --max-output count")?;
+                limited = true;
+            }
+            #[cfg(feature = "dot")]
+            "--loop-dot" => loop_dot = Some(args.next().ok_or("--loop-dot needs a file")?),
+            #[cfg(feature = "plot")]
+            "--plot-opcodes" => {
+                plot_opcodes = Some(args.next().ok_or("--plot-opcodes needs a file")?);
+            }
+            #[cfg(feature = "llvm")]
+            "--emit-llvm" => {
+                emit_llvm = Some(args.next().ok_or("--emit-llvm needs a file")?);
+            }
+            "--bench-runs" => {
+                let runs = args.next().ok_or("--bench-runs needs a count")?;
+                bench_runs = Some(runs.parse().map_err(|_| "invalid --bench-runs value")?);
+            }
+            "--save-profile" => {
+                save_profile = Some(args.next().ok_or("--save-profile needs a file")?);
+                profile.get_or_insert_with(Profile::default);
+            }
+            "--compare-profile" => {
+                let before = args.next().ok_or("--compare-profile needs two files")?;
+                let after = args.next().ok_or("--compare-profile needs two files")?;
+                compare_profile = Some((before, after));
+            }
+            "--gen-seed" => {
+                let seed = args.next().ok_or("--gen-seed needs a seed")?;
+                gen_seed = Some(seed.parse().map_err(|_| "invalid --gen-seed value")?);
+            }
+            "--gen-len" => {
+                let len = args.next().ok_or("--gen-len needs a length")?;
+                gen_len = len.parse().map_err(|_| "invalid --gen-len value")?;
+            }
+            "--list-engines" => {
+                list_engines();
+                return Ok(());
+            }
+            "--verify-against" => {
+                match args
+                    .next()
+                    .ok_or("--verify-against needs an engine")?
+                    .as_str()
+                {
+                    "raw" => verify_against = Some(RawEngine::NAME),
+                    _ => return Err("unknown engine for --verify-against, expected raw"),
+                }
+            }
+            _ => positional.push(arg),
+        }
+    }
+    if let Some((before, after)) = compare_profile {
+        let load = |path| {
+            Profile::from_json(
+                &std::fs::read_to_string(path).map_err(|_| "could not read profile")?,
+            )
+        };
+        for (pc, delta) in load(before)?.diff(&load(after)?) {
+            println!("{pc:>5}: {delta:>+9}");
+        }
+        return Ok(());
+    }
+    if let Some(command) = archive {
+        return run_archive(&command, &positional, &config, quiet);
+    }
+    if let Some(seed) = gen_seed {
+        let program: Vec<u8> = random_program::generate(seed, gen_len)
+            .into_iter()
+            .map(BasicOpcode::to_byte)
+            .collect();
+        let mut stdout = std::io::stdout().lock();
+        let _ = stdout.write_all(&program);
+        let _ = stdout.write_all(b"\n");
+        return Ok(());
+    }
+    if limited && engine.is_none() {
+        return Err("--max-steps, --timeout and --max-output need --engine");
+    }
+    if asm && verify_against.is_some() {
+        return Err("--verify-against needs Brainfuck source, not --asm");
+    }
+    // `<file> [input]`, or `<files...> -- [input]` to run several files one
+    // after the other as a single program. Brackets may span files.
+    let (paths, input_arg) = match positional.iter().position(|arg| arg == "--") {
+        Some(split) => {
+            let input_arg = positional.get(split + 1).cloned();
+            positional.truncate(split);
+            (positional, input_arg)
+        }
+        None => {
+            let mut args = positional.into_iter();
+            (args.next().into_iter().collect(), args.next())
+        }
+    };
+    if paths.is_empty() {
+        return Err("input file missing");
+    }
+    let files = paths
+        .iter()
+        .map(std::fs::File::open)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| "could not open file")?;
+    let read_source = || {
+        let mut src = Vec::new();
+        for mut file in &files {
+            file.read_to_end(&mut src)
+                .map_err(|_| "could not read file")?;
+        }
+        Ok::<_, &'static str>(src)
+    };
+
+    // Without an input argument the program reads stdin as it goes.
+    let mut input: Box<dyn Read> = match input_arg {
+        Some(input) => Box::new(std::io::Cursor::new(input.into_bytes())),
+        None => Box::new(std::io::stdin().lock()),
+    };
+    // Verifying, benchmarking and checkpointing replay the same input, so it
+    // has to be read up front.
+    let mut recorded_input = Vec::new();
+    if verify_against.is_some() || bench_runs.is_some() || checkpoint.is_some() || restore.is_some()
+    {
+        input
+            .read_to_end(&mut recorded_input)
+            .map_err(|_| "could not read input")?;
+        input = Box::new(std::io::Cursor::new(recorded_input.clone()));
+    }
+    if echo_input {
+        input = Box::new(EchoInput {
+            inner: input,
+            count: 0,
+        });
+    }
+    // Any engine by name, but without the extras the default engine gets below,
+    // and with all of the input read before the program starts.
+    if let Some(name) = engine {
+        let src = read_source()?;
+        let mut bytes = Vec::new();
+        input
+            .read_to_end(&mut bytes)
+            .map_err(|_| "could not read input")?;
+        let src = String::from_utf8_lossy(&src);
+        let outcome =
+            compile_and_run_by_name(&name, &src, &bytes, &config, limits).map_err(|e| {
+                eprintln!("{e}");
+                "could not run program"
+            })?;
+        let _ = std::io::stdout().lock().write_all(&outcome.output);
+        if !quiet {
+            eprintln!("exit       {:?}", outcome.exit);
+        }
+        if let Some(limit) = outcome.limit {
+            eprintln!("stopped    {limit}");
+        }
+        return Ok(());
+    }
 
-    let basic_opcodes_iter = std::fs::File::open(args.next().ok_or("input file missing")?)
-        .map_err(|_| "could not open file")?
-        .bytes()
-        .filter_map(Result::ok)
-        .filter_map(to_basic_opcode);
+    let start = Instant::now();
+    let mut asm_src = String::new();
+    let opcodes: Vec<_> = if asm {
+        asm_src = String::from_utf8(read_source()?).map_err(|_| "could not read file")?;
+        Vec::new()
+    } else {
+        let total = files
+            .iter()
+            .map(|file| file.metadata().map_or(0, |m| m.len()))
+            .sum::<u64>()
+            .max(1);
+        let mut parsed = progress.then(|| {
+            Progress::new(1 << 20, move |n| {
+                eprint!("\rparsed {n}/{total} bytes ({}%)", n * 100 / total)
+            })
+        });
+        #[cfg(all(feature = "mmap", unix))]
+        let mappings = files
+            .iter()
+            .map(mapped::Mapping::new)
+            .collect::<Result<Vec<_>, _>>()?;
+        #[cfg(all(feature = "mmap", unix))]
+        let bytes = mappings
+            .iter()
+            .flat_map(|mapping| mapping.bytes().iter().copied());
+        #[cfg(not(all(feature = "mmap", unix)))]
+        let bytes = files
+            .iter()
+            .flat_map(|file| std::io::BufReader::new(file).bytes().filter_map(Result::ok));
+        let opcodes = bytes
+            .inspect(|_| {
+                if let Some(parsed) = &mut parsed {
+                    parsed.tick()
+                }
+            })
+            .filter_map(|c| match extended {
+                true => ExtendedTypeI::command(c),
+                false => Brainfuck::command(c),
+            })
+            .collect();
+        if parsed.is_some_and(|parsed| parsed.count() >= 1 << 20) {
+            eprintln!();
+        }
+        opcodes
+    };
+    let parse = start.elapsed();
+    if lint {
+        for i in lint::unprompted_reads(&opcodes) {
+            eprintln!("lint: command {i} reads input without printing a prompt first");
+        }
+    }
+    #[cfg(feature = "dot")]
+    if let Some(path) = loop_dot {
+        if asm {
+            return Err("--loop-dot needs Brainfuck source, not --asm");
+        }
+        let dot = loop_graph::loop_tree_dot(&opcodes)?;
+        std::fs::write(path, dot).map_err(|_| "could not write graph")?;
+    }
+    if dump_optimizations {
+        if asm {
+            return Err("--dump-optimizations needs Brainfuck source, not --asm");
+        }
+        return dump_optimized(&opcodes);
+    }
+    if echo_source {
+        if asm {
+            return Err("--echo-source needs Brainfuck source, not --asm");
+        }
+        let source: Vec<u8> = opcodes.iter().copied().map(BasicOpcode::to_byte).collect();
+        let mut stdout = std::io::stdout().lock();
+        let _ = stdout.write_all(&source);
+        let _ = stdout.write_all(b"\n");
+        return Ok(());
+    }
+
+    let generate = || {
+        let mut compiled = if asm {
+            assemble(&asm_src)?
+        } else {
+            let (mut compiled, log) =
+                ShiftAddEngine::generate_at(opcodes.iter().copied(), opt_level)
+                    .map_err(String::from)?;
+            if opt_log {
+                eprint!("{log}");
+            }
+            if warn_unoptimized_loops {
+                for (open, close) in matching_brackets(&opcodes) {
+                    if log.plain_loops.binary_search(&close).is_ok() {
+                        eprintln!("warning: loop at commands {open}..={close} stays a plain loop");
+                    }
+                }
+            }
+            if opt_level == OptLevel::O2 {
+                let removed = remove_dead_code(&mut compiled, &config);
+                if removed > 0 {
+                    eprintln!("warning: removed {removed} opcodes after a loop that never exits");
+                }
+                fold_constant_loops(&mut compiled, &config);
+            }
+            if blocks {
+                compiled = group_blocks(&compiled);
+            }
+            compiled
+        };
+        if strip_unreachable {
+            let removed = remove_unreachable(&mut compiled);
+            if !quiet {
+                eprintln!("removed {removed} unreachable opcodes");
+            }
+        } else {
+            let unreachable = unreachable_opcodes(&compiled);
+            if let Some(first) = unreachable.first() {
+                eprintln!(
+                    "warning: {} opcodes can never run, starting at {first}",
+                    unreachable.len()
+                );
+            }
+        }
+        Ok(compiled)
+    };
+
+    #[cfg(feature = "llvm")]
+    if let Some(path) = emit_llvm {
+        let compiled = generate().map_err(|e| {
+            eprintln!("{e}");
+            "could not compile program"
+        })?;
+        std::fs::write(path, llvm_ir::emit(&compiled, &config))
+            .map_err(|_| "could not write LLVM IR")?;
+        return Ok(());
+    }
 
-    let input = args.next().unwrap_or(String::new());
-    let input = input.as_bytes();
+    if checkpoint.is_some() || restore.is_some() {
+        if asm {
+            return Err("--checkpoint and --restore need Brainfuck source, not --asm");
+        }
+        let status = run_checkpointed(
+            &opcodes,
+            recorded_input,
+            config,
+            checkpoint.as_deref(),
+            checkpoint_every,
+            restore.as_deref(),
+        )?;
+        eprintln!("exit {status:?}");
+        return Ok(());
+    }
 
-    let opcodes: Vec<_> = basic_opcodes_iter.collect();
+    if let Some(runs) = bench_runs {
+        let compiled = generate().map_err(|e| {
+            eprintln!("{e}");
+            "could not compile program"
+        })?;
+        bench(&compiled, &recorded_input, &config, runs, profile.as_mut());
+        if let (Some(profile), true) = (&profile, show_profile) {
+            print_profile(&compiled, profile, indent);
+        }
+        return Ok(());
+    }
 
-    let start = Instant::now();
+    // `Stdout` is line buffered already.
+    let stdout = std::io::stdout().lock();
+    let stdout: Box<dyn Write> = match output_buffering.as_str() {
+        "line" => Box::new(stdout),
+        "block" => Box::new(std::io::BufWriter::new(stdout)),
+        "none" => Box::new(Unbuffered(stdout)),
+        _ => return Err("--output-buffering needs line, block or none"),
+    };
+    let output: Box<dyn Write> = if hex_output {
+        Box::new(HexDump::new(stdout))
+    } else {
+        Box::new(stdout)
+    };
+    let output: Box<dyn Write> = if strip_control.is_empty() {
+        output
+    } else {
+        Box::new(StripControl::new(output, &strip_control)?)
+    };
+    let output: Box<dyn Write> = match output_tail {
+        Some(len) => Box::new(Tail::new(output, len)),
+        None => output,
+    };
+    let mut output = Tee {
+        inner: output,
+        copy: verify_against.map(|_| Vec::new()),
+    };
+
+    if extended {
+        // Only the Raw engine knows the extra commands.
+        let (mut report, _, _) = compile_execute::<RawEngine>(
+            || RawEngine::generate(opcodes.iter().copied()).map_err(String::from),
+            &mut input,
+            &mut output,
+            &config,
+            tape_file.as_deref(),
+            &mut (),
+        )
+        .map_err(|e| {
+            eprintln!("{e}");
+            "could not compile program"
+        })?;
+        report.parse = parse;
+        if !quiet {
+            eprint!("{report}");
+        }
+        return Ok(());
+    }
 
     let mut reports = Vec::new();
 
-    reports.push(dbg!(compile_execute::<ShiftAddEngine>(
-        opcodes.iter().copied(),
-        input
-    )));
+    #[cfg(all(feature = "signal", unix))]
+    let mut interrupt = Some(interrupt::Watch::install());
+    #[cfg(not(all(feature = "signal", unix)))]
+    let mut interrupt: Option<()> = None;
+
+    let mut executed =
+        progress.then(|| Progress::new(1 << 26, |n| eprint!("\rexecuted {n} opcodes")));
+
+    let mut basic_ops = show_profile.then(|| BasicOpEstimate::new(&config));
+
+    // Only pay for observing the run when something was asked for.
+    let observed = stats.is_some()
+        || profile.is_some()
+        || interrupt.is_some()
+        || executed.is_some()
+        || warn_wrap.is_some()
+        || quiescent.is_some();
+    let observer = &mut (
+        &mut stats,
+        (
+            &mut profile,
+            (
+                &mut interrupt,
+                (
+                    &mut executed,
+                    (&mut warn_wrap, (&mut quiescent, &mut basic_ops)),
+                ),
+            ),
+        ),
+    );
+    let (mut report, compiled, tape) = match (checked, observed) {
+        (true, _) => compile_execute::<CheckedShiftAddEngine>(
+            generate,
+            &mut input,
+            &mut output,
+            &config,
+            tape_file.as_deref(),
+            observer,
+        ),
+        (false, true) => compile_execute::<ShiftAddEngine>(
+            generate,
+            &mut input,
+            &mut output,
+            &config,
+            tape_file.as_deref(),
+            observer,
+        ),
+        (false, false) => compile_execute::<ShiftAddEngine>(
+            generate,
+            &mut input,
+            &mut output,
+            &config,
+            tape_file.as_deref(),
+            &mut (),
+        ),
+    }
+    .map_err(|e| {
+        // Point at the bracket, which means going back to the source.
+        let src = match asm {
+            true => Vec::new(),
+            false => paths
+                .iter()
+                .flat_map(|path| std::fs::read(path).unwrap_or_default())
+                .collect(),
+        };
+        match check_brackets(&src) {
+            Err(bracket) => eprintln!("{}", bracket.with_source(&src)),
+            Ok(()) => eprintln!("{e}"),
+        }
+        "could not compile program"
+    })?;
+    if executed.is_some_and(|executed| executed.count() >= 1 << 26) {
+        eprintln!();
+    }
+    report.parse = parse;
+    let exit_status = report.exit_status;
+    reports.push(report);
     //reports.push(dbg!(compile_execute::<MergeTokenEngineExtra>(
-    //    opcodes.iter().copied(),
-    //    input
+    //    || MergeTokenEngineExtra::generate(opcodes.iter().copied()).map_err(String::from),
+    //    &mut input,
+    //    &mut output,
+    //    &config,
+    //    &mut (),
     //)));
     //reports.push(dbg!(compile_execute::<MergeTokenEngine>(
-    //    opcodes.iter().copied(),
-    //    input
+    //    || MergeTokenEngine::generate(opcodes.iter().copied()).map_err(String::from),
+    //    &mut input,
+    //    &mut output,
+    //    &config,
+    //    &mut (),
     //)));
 
-    dbg!(reports);
+    if trailing_newline
+        && reports
+            .iter()
+            .all(|report| report.exit_status == ExitStatus::Halted)
+    {
+        let _ = output.write_all(b"\n");
+    }
+    let Tee {
+        inner: output,
+        copy: fast_output,
+    } = output;
+    drop(output);
+
+    if let Some(fast_output) = fast_output {
+        if matches!(
+            exit_status,
+            ExitStatus::Interrupted | ExitStatus::Quiescent | ExitStatus::SeekLimit
+        ) {
+            eprintln!("warning: run was stopped early, skipping verification");
+        } else {
+            let raw = RawEngine::generate(opcodes.iter().copied())?;
+            let mut raw_output = Vec::new();
+            let (_, raw_tape) = RawEngine::execute_fresh(
+                &raw,
+                &mut recorded_input.as_slice(),
+                &mut raw_output,
+                &config,
+            );
+            verify(&fast_output, &tape[..], &raw_output, &raw_tape[..]);
+        }
+    }
 
-    println!("elapsed total: {:?}", start.elapsed());
+    if !quiet {
+        for report in &reports {
+            eprint!("{report}");
+        }
+    }
+    #[cfg(feature = "plot")]
+    if let Some(path) = plot_opcodes {
+        let svg = plot::opcode_histogram_svg(&OpcodeStats::from_opcodes(&compiled));
+        std::fs::write(path, svg).map_err(|_| "could not write chart")?;
+    }
+    if mem_stats {
+        print_mem_stats(&compiled, profile.as_ref());
+    }
+    if let Some(profile) = profile {
+        if let Some(path) = save_profile {
+            std::fs::write(path, profile.to_json()).map_err(|_| "could not save profile")?;
+        }
+        if show_profile {
+            print_profile(&compiled, &profile, indent);
+        }
+        if let Some(estimate) = basic_ops {
+            eprintln!(
+                "basic ops: about {} for {} dispatched, {:.1}x",
+                estimate.basic_ops(&compiled),
+                estimate.dispatched(),
+                estimate.speedup(&compiled)
+            );
+        }
+    }
+    if let Some(stats) = stats {
+        eprintln!("tape used: cells 0..={} of {DATA_LEN}", stats.max_dp);
+    }
 
     Ok(())
 }
 
-use merge_token_engine_extra::*;
-mod merge_token_engine_extra {
-    use super::{BasicOpcode, BfEngine, Write, DATA_LEN};
-
-    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
-    pub(super) enum Opcode {
-        Add(u8),
-        /// Open
-        BranchZero(u16),
-        /// Close
-        BranchNotZero(u16),
-        Right(i16),
-        Dot,
-        Comma,
-
-        Clear,
-        AddTo(i16),
-        Seek(i16),
-    }
-
-    pub(super) struct MergeTokenEngineExtra {}
-    impl BfEngine for MergeTokenEngineExtra {
-        const NAME: &'static str = "Merge tokens extra";
-
-        type OPCODE = Opcode;
-
-        fn generate(
-            iter: impl Iterator<Item = BasicOpcode>,
-        ) -> Result<Vec<Self::OPCODE>, &'static str> {
-            let mut iter = iter;
-            let mut buffer = Vec::new();
-            use Opcode::*;
-            let mut open_stack: Vec<usize> = Vec::new();
-            loop {
-                let Some(opcode) = iter.next() else { break };
-
-                match opcode {
-                    BasicOpcode::Add => {
-                        if let Some(Add(p)) = buffer.last_mut() {
-                            *p = p.wrapping_add(1);
-                        } else {
-                            buffer.push(Add(1))
-                        }
-                    }
-                    BasicOpcode::Sub => {
-                        if let Some(Add(p)) = buffer.last_mut() {
-                            *p = p.wrapping_sub(1)
-                        } else {
-                            buffer.push(Add(-1_i32 as _))
-                        }
-                    }
-                    BasicOpcode::Right => {
-                        if let Some(Right(p)) = buffer.last_mut() {
-                            *p = p.wrapping_add(1)
-                        } else {
-                            buffer.push(Right(1))
-                        }
-                    }
-                    BasicOpcode::Left => {
-                        if let Some(Right(p)) = buffer.last_mut() {
-                            *p = p.wrapping_sub(1)
-                        } else {
-                            buffer.push(Right(-1 as _))
-                        }
-                    }
-                    BasicOpcode::Open => {
-                        open_stack.push(buffer.len());
-                        buffer.push(BranchZero(0));
-                    }
-                    BasicOpcode::Close => {
-                        let other = open_stack.pop().ok_or("unbalanced brackets: extra ]")?;
-                        let this = buffer.len();
-                        buffer[other] = BranchZero(this.try_into().unwrap());
-
-                        match &buffer[..] {
-                            &[.., BranchZero(_), Add(255)] => {
-                                buffer.truncate(buffer.len() - 2);
-                                buffer.push(Clear);
-                            }
-                            &[.., BranchZero(_), Add(255), Right(x), Add(1), Right(y)]
-                                if -x == y =>
-                            {
-                                buffer.truncate(buffer.len() - 5);
-                                buffer.push(AddTo(x));
-                            }
-                            &[.., BranchZero(_), Right(x)] => {
-                                buffer.push(Seek(x));
-                            }
-                            _ => {
-                                buffer.push(BranchNotZero(other.try_into().unwrap()));
-                            }
-                        }
-                    }
-                    BasicOpcode::Dot => buffer.push(Dot),
-                    BasicOpcode::Comma => buffer.push(Comma),
+/// Prints how much memory the run needed: the compiled program, the tape and
+/// the profile if there was one.
+fn print_mem_stats(opcodes: &[shift_add_engine::Opcode], profile: Option<&Profile>) {
+    let opcode_bytes = std::mem::size_of_val(opcodes);
+    let profile_bytes = profile.map_or(0, Profile::heap_bytes);
+    eprintln!(
+        "opcodes: {opcode_bytes} bytes ({} x {})",
+        opcodes.len(),
+        std::mem::size_of::<shift_add_engine::Opcode>()
+    );
+    eprintln!("tape: {DATA_LEN} bytes");
+    if profile.is_some() {
+        eprintln!("profile: {profile_bytes} bytes");
+    }
+    eprintln!("total: {} bytes", opcode_bytes + DATA_LEN + profile_bytes);
+}
+
+/// Runs `opcodes` `runs` times on a fresh tape, discarding the output, and
+/// prints the spread of execution times. With a `profile`, every run is
+/// profiled into the same buffers, which end up holding the last run.
+fn bench(
+    opcodes: &[shift_add_engine::Opcode],
+    input: &[u8],
+    config: &RunConfig,
+    runs: usize,
+    mut profile: Option<&mut Profile>,
+) {
+    let mut data = new_tape(config);
+    if let Some(profile) = profile.as_deref_mut() {
+        *profile = Profile::with_len(opcodes.len());
+    }
+    let mut durations: Vec<Duration> = (0..runs)
+        .map(|_| {
+            data.fill(config.tape_fill);
+            let start = Instant::now();
+            match profile.as_deref_mut() {
+                Some(profile) => {
+                    profile.reset();
+                    ShiftAddEngine::execute_observed(
+                        opcodes,
+                        &mut data,
+                        &mut &input[..],
+                        &mut std::io::sink(),
+                        config,
+                        profile,
+                    );
                 }
-                match &buffer[..] {
-                    &[.., Add(0)] | &[.., Right(0)] | &[.., AddTo(_), Clear] => {
-                        println!("found redundant code {:?}", buffer);
-                        let _ = buffer.pop();
-                    }
-                    _ => (),
+                None => {
+                    ShiftAddEngine::execute(
+                        opcodes,
+                        &mut data,
+                        &mut &input[..],
+                        &mut std::io::sink(),
+                        config,
+                    );
                 }
             }
+            start.elapsed()
+        })
+        .collect();
+    durations.sort();
+    let (Some(min), Some(max)) = (durations.first(), durations.last()) else {
+        return;
+    };
+    let median = durations[durations.len() / 2];
+    let mean = durations.iter().sum::<Duration>() / runs as u32;
+    println!("{runs} runs: min {min:?} median {median:?} max {max:?} mean {mean:?}");
+}
+
+/// Runs on an [`Interpreter`], saving its state to `checkpoint` every `every`
+/// opcodes and at the end, after starting from `restore` if given.
+fn run_checkpointed(
+    opcodes: &[BasicOpcode],
+    input: Vec<u8>,
+    config: RunConfig,
+    checkpoint: Option<&str>,
+    every: u64,
+    restore: Option<&str>,
+) -> Result<ExitStatus, &'static str> {
+    let stdout = std::io::stdout().lock();
+    let mut interpreter = Interpreter::new(opcodes.iter().copied(), input, stdout, config)?;
+    if let Some(path) = restore {
+        let bytes = std::fs::read(path).map_err(|_| "could not read saved state")?;
+        interpreter.restore_state(&StateSnapshot::from_bytes(&bytes)?)?;
+    }
+    let save = |interpreter: &Interpreter<_>| match checkpoint {
+        Some(path) => std::fs::write(path, interpreter.save_state().to_bytes())
+            .map_err(|_| "could not write checkpoint"),
+        None => Ok(()),
+    };
+    loop {
+        let steps = std::cell::Cell::new(0);
+        let status = interpreter.run_until(|_, _| {
+            steps.set(steps.get() + 1);
+            steps.get() >= every
+        });
+        save(&interpreter)?;
+        if let Some(status) = status {
+            return Ok(status);
+        }
+    }
+}
 
-            if !open_stack.is_empty() {
-                return Err("unbalanced brackets: extra [");
-            } else {
-                Ok(buffer)
+/// `--archive pack <out> <files...>` bundles programs named after their files;
+/// `--archive run <file> <name> [input]` runs one of them.
+fn run_archive(
+    command: &str,
+    args: &[String],
+    config: &RunConfig,
+    quiet: bool,
+) -> Result<(), &'static str> {
+    match (command, args) {
+        ("pack", [out, files @ ..]) => {
+            let mut programs = Vec::new();
+            for file in files {
+                let src = std::fs::read(file).map_err(|_| "could not open file")?;
+                let name = std::path::Path::new(file)
+                    .file_stem()
+                    .map_or(file.clone(), |stem| stem.to_string_lossy().into_owned());
+                programs.push((name, String::from_utf8_lossy(&src).into_owned()));
             }
+            let programs: Vec<_> = programs
+                .iter()
+                .map(|(name, src)| (name.clone(), src.as_str()))
+                .collect();
+            std::fs::write(out, archive::pack(&programs)).map_err(|_| "could not write archive")
         }
+        ("run", [file, name, input @ ..]) => {
+            let bytes = std::fs::read(file).map_err(|_| "could not open file")?;
+            let programs = archive::unpack(&bytes)?;
+            let (_, src) = programs
+                .iter()
+                .find(|(entry, _)| entry == name)
+                .ok_or("no program with that name in the archive")?;
+            let mut input: Box<dyn Read> = match input.first() {
+                Some(input) => Box::new(std::io::Cursor::new(input.clone().into_bytes())),
+                None => Box::new(std::io::stdin().lock()),
+            };
+            let (report, _, _) = compile_execute::<ShiftAddEngine>(
+                || {
+                    compile_from_str::<ShiftAddEngine>(src)
+                        .map_err(|e| e.with_source(src.as_bytes()).to_string())
+                },
+                &mut input,
+                &mut std::io::stdout().lock(),
+                config,
+                None,
+                &mut (),
+            )
+            .map_err(|e| {
+                eprintln!("{e}");
+                "could not compile program"
+            })?;
+            if !quiet {
+                eprint!("{report}");
+            }
+            Ok(())
+        }
+        ("pack" | "run", _) => {
+            Err("--archive pack needs <out> <files...>, run needs <file> <name>")
+        }
+        _ => Err("unknown --archive command, expected pack or run"),
+    }
+}
 
-        fn execute(
-            opcodes: &[Self::OPCODE],
-            data: &mut [u8; DATA_LEN],
-            input: &[u8],
-            stdout: &mut std::io::StdoutLock<'static>,
-        ) {
-            let mut input = input.iter().copied();
-            let mut pc: usize = 0;
-            let mut dp: usize = 0;
-            loop {
-                let Some(opcode) = opcodes.get(pc) else { break };
-
-                match opcode {
-                    Opcode::Add(i) => {
-                        data[dp] = data[dp].wrapping_add(*i);
-                    }
-                    Opcode::BranchZero(i) => {
-                        if data[dp] == 0 {
-                            pc = *i as _;
-                        }
-                    }
-                    Opcode::BranchNotZero(i) => {
-                        if data[dp] != 0 {
-                            pc = *i as _;
-                        }
-                    }
-                    Opcode::Right(i) => {
-                        dp = dp.wrapping_add(*i as _);
-                    }
-                    Opcode::Dot => {
-                        let _ = stdout.write(&[data[dp]]);
-                    }
-                    Opcode::Comma => {
-                        data[dp as usize] = input.next().unwrap();
-                    }
-                    Opcode::Clear => data[dp as usize] = 0,
-                    Opcode::AddTo(i) => {
-                        let from = dp as usize;
-                        let to = ((dp.wrapping_add(*i as _)) as usize) % DATA_LEN;
+fn list_engines() {
+    fn line<E: BfEngine>(default: bool) {
+        let default = if default { " (default)" } else { "" };
+        println!("{:<20} {}{default}", E::NAME, E::DESCRIPTION);
+        if !E::OPTIMIZATIONS.is_empty() {
+            println!("{:<20} {}", "", E::OPTIMIZATIONS.join(", "));
+        }
+    }
+    line::<ShiftAddEngine>(true);
+    line::<CheckedShiftAddEngine>(false);
+    line::<SparseEngine>(false);
+    line::<MergeTokenEngineExtra>(false);
+    line::<MergeTokenEngine>(false);
+    line::<RawEngine>(false);
+}
 
-                        let tmp = data[from];
+/// Forwards everything to `inner`, keeping a copy when `copy` is set.
+struct Tee<W: Write> {
+    inner: W,
+    copy: Option<Vec<u8>>,
+}
 
-                        data[from] = tmp.wrapping_sub(tmp);
-                        data[to] = data[to].wrapping_add(tmp);
-                    }
-                    Opcode::Seek(i) => {
-                        while data[dp as usize] != 0 {
-                            dp = dp.wrapping_add(*i as _);
-                        }
-                    }
-                }
-                pc += 1
-            }
+impl<W: Write> Write for Tee<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let Some(copy) = &mut self.copy {
+            copy.extend_from_slice(&buf[..written]);
         }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
     }
 }
 
-use merge_token_engine::*;
-mod merge_token_engine {
-    use super::{BasicOpcode, BfEngine, Write, DATA_LEN};
-
-    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
-    pub(super) enum Opcode {
-        Add(u8),
-        /// Open
-        BranchZero(u16),
-        /// Close
-        BranchNotZero(u16),
-        Right(i16),
-        Dot,
-        Comma,
-    }
-
-    pub(super) struct MergeTokenEngine {}
-    impl BfEngine for MergeTokenEngine {
-        const NAME: &'static str = "Merge tokens basic";
-
-        type OPCODE = Opcode;
-
-        fn generate(
-            iter: impl Iterator<Item = BasicOpcode>,
-        ) -> Result<Vec<Self::OPCODE>, &'static str> {
-            let mut iter = iter;
-            let mut buffer = Vec::new();
-            use Opcode::*;
-            let mut open_stack: Vec<usize> = Vec::new();
-            loop {
-                let Some(opcode) = iter.next() else { break };
-
-                match opcode {
-                    BasicOpcode::Add => {
-                        if let Some(Add(p)) = buffer.last_mut() {
-                            *p = p.wrapping_add(1);
-                        } else {
-                            buffer.push(Add(1))
-                        }
-                    }
-                    BasicOpcode::Sub => {
-                        if let Some(Add(p)) = buffer.last_mut() {
-                            *p = p.wrapping_sub(1)
-                        } else {
-                            buffer.push(Add(-1_i32 as _))
-                        }
-                    }
-                    BasicOpcode::Right => {
-                        if let Some(Right(p)) = buffer.last_mut() {
-                            *p = p.wrapping_add(1)
-                        } else {
-                            buffer.push(Right(1))
-                        }
-                    }
-                    BasicOpcode::Left => {
-                        if let Some(Right(p)) = buffer.last_mut() {
-                            *p = p.wrapping_sub(1)
-                        } else {
-                            buffer.push(Right(-1 as _))
-                        }
-                    }
-                    BasicOpcode::Open => {
-                        open_stack.push(buffer.len());
-                        buffer.push(BranchZero(0));
-                    }
-                    BasicOpcode::Close => {
-                        let other = open_stack.pop().ok_or("unbalanced brackets: extra ]")?;
-                        let this = buffer.len();
-                        buffer[other] = BranchZero(this.try_into().unwrap());
-
-                        match &buffer[..] {
-                            _ => {
-                                buffer.push(BranchNotZero(other.try_into().unwrap()));
-                            }
-                        }
-                    }
-                    BasicOpcode::Dot => buffer.push(Dot),
-                    BasicOpcode::Comma => buffer.push(Comma),
+/// Flushes `0` after every write, for `--output-buffering none`. Engines
+/// write each `.` on its own, so that's a system call per output byte: about
+/// 25 times slower than `block` into a pipe for output-heavy programs. Reads
+/// are unaffected, engines flush before every `,` whatever the buffering.
+struct Unbuffered<W: Write>(W);
+
+impl<W: Write> Write for Unbuffered<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.0.write(buf)?;
+        self.0.flush()?;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Lists each optimized opcode next to the same commands with only runs of
+/// `+-` and `<>` merged, starring the ones a peephole rule produced and
+/// showing commands that were optimized away entirely.
+fn dump_optimized(opcodes: &[BasicOpcode]) -> Result<(), &'static str> {
+    use shift_add_engine::Opcode;
+    let src: Vec<u8> = opcodes.iter().copied().map(BasicOpcode::to_byte).collect();
+    let (compiled, spans) = ShiftAddEngine::generate_spanned(&src)?;
+    let mut covered = 0;
+    for (pc, (opcode, span)) in compiled.iter().zip(&spans).enumerate() {
+        if span.start > covered {
+            println!(
+                "  - {:>5}  {:<28} {}",
+                "",
+                "(removed)",
+                merged(&src[covered..span.start])
+            );
+        }
+        covered = covered.max(span.end);
+        let plain = matches!(
+            opcode,
+            Opcode::AddRight(..)
+                | Opcode::BranchZero(_)
+                | Opcode::BranchNotZero(_)
+                | Opcode::Dot
+                | Opcode::Comma
+                | Opcode::Exit
+        );
+        let mark = if plain { ' ' } else { '*' };
+        let opcode = format!("{opcode:?}");
+        let line = format!(
+            "  {mark} {pc:>5}  {opcode:<28} {}",
+            merged(&src[span.start..span.end])
+        );
+        println!("{}", line.trim_end());
+    }
+    Ok(())
+}
+
+/// `commands` with runs of `+-` and `<>` summed, like `Add(3) Right(-1) [`.
+fn merged(commands: &[u8]) -> String {
+    let mut tokens: Vec<String> = Vec::new();
+    let mut run: Option<(u8, i32)> = None;
+    for &c in commands.iter().chain([&0]) {
+        let (kind, step) = match c {
+            b'+' => (b'+', 1),
+            b'-' => (b'+', -1),
+            b'>' => (b'>', 1),
+            b'<' => (b'>', -1),
+            _ => (c, 0),
+        };
+        match &mut run {
+            Some((run_kind, sum)) if *run_kind == kind && step != 0 => *sum += step,
+            _ => {
+                match run.take() {
+                    Some((b'+', sum)) => tokens.push(format!("Add({sum})")),
+                    Some((_, sum)) => tokens.push(format!("Right({sum})")),
+                    None => (),
                 }
-                match &buffer[..] {
-                    &[.., Add(0)] | &[.., Right(0)] => {
-                        println!("found redundant code {:?}", buffer);
-                        let _ = buffer.pop();
-                    }
-                    _ => (),
+                match c {
+                    0 => (),
+                    _ if step != 0 => run = Some((kind, step)),
+                    _ => tokens.push((c as char).to_string()),
                 }
             }
+        }
+    }
+    tokens.join(" ")
+}
 
-            if !open_stack.is_empty() {
-                return Err("unbalanced brackets: extra [");
-            } else {
-                Ok(buffer)
+/// Command indices of each `[` and its `]`, in order of the `]`.
+fn matching_brackets(opcodes: &[BasicOpcode]) -> Vec<(usize, usize)> {
+    let mut open = Vec::new();
+    let mut pairs = Vec::new();
+    for (i, opcode) in opcodes.iter().enumerate() {
+        match opcode {
+            BasicOpcode::Open => open.push(i),
+            BasicOpcode::Close => pairs.extend(open.pop().map(|start| (start, i))),
+            _ => (),
+        }
+    }
+    pairs
+}
+
+/// Reports every byte read from `inner` on stderr.
+struct EchoInput<R: Read> {
+    inner: R,
+    count: usize,
+}
+
+impl<R: Read> Read for EchoInput<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        for &byte in &buf[..read] {
+            eprintln!(
+                "input {:>5}: {byte:>3} '{}'",
+                self.count,
+                byte.escape_ascii()
+            );
+            self.count += 1;
+        }
+        Ok(read)
+    }
+}
+
+/// Warns about the first place the run differs from the reference engine.
+fn verify(output: &[u8], tape: &[u8], raw_output: &[u8], raw_tape: &[u8]) {
+    let first_difference =
+        |a: &[u8], b: &[u8]| (0..a.len().max(b.len())).find(|&i| a.get(i) != b.get(i));
+    let mut matches = true;
+    if let Some(i) = first_difference(output, raw_output) {
+        eprintln!(
+            "warning: output differs from the {} engine at byte {i} ({} vs {} bytes written)",
+            RawEngine::NAME,
+            output.len(),
+            raw_output.len()
+        );
+        matches = false;
+    }
+    if let Some(i) = first_difference(tape, raw_tape) {
+        eprintln!(
+            "warning: tape differs from the {} engine at cell {i} ({} vs {})",
+            RawEngine::NAME,
+            tape[i],
+            raw_tape[i]
+        );
+        matches = false;
+    }
+    if matches {
+        eprintln!("verified against the {} engine", RawEngine::NAME);
+    }
+}
+
+/// Reads the program straight out of the page cache instead of copying it
+/// through a buffer, which matters for generated programs hundreds of megabytes
+/// long. Declares `mmap` itself rather than using memmap2, so the feature adds
+/// no dependency.
+#[cfg(all(feature = "mmap", unix))]
+mod mapped {
+    use bf_interpreter::DATA_LEN;
+    use std::fs::File;
+    use std::os::fd::AsRawFd;
+
+    const PROT_READ: i32 = 1;
+    const PROT_WRITE: i32 = 2;
+    const MAP_SHARED: i32 = 1;
+    const MAP_PRIVATE: i32 = 2;
+
+    extern "C" {
+        fn mmap(addr: *mut u8, len: usize, prot: i32, flags: i32, fd: i32, offset: i64) -> *mut u8;
+        fn munmap(addr: *mut u8, len: usize) -> i32;
+    }
+
+    pub struct Mapping {
+        ptr: *mut u8,
+        len: usize,
+    }
+
+    impl Mapping {
+        pub fn new(file: &File) -> Result<Self, &'static str> {
+            let len = file
+                .metadata()
+                .map_err(|_| "could not read file")?
+                .len()
+                .try_into()
+                .map_err(|_| "file too large to map")?;
+            if len == 0 {
+                // `mmap` refuses empty mappings.
+                return Ok(Self {
+                    ptr: std::ptr::null_mut(),
+                    len,
+                });
             }
+            let ptr = unsafe {
+                mmap(
+                    std::ptr::null_mut(),
+                    len,
+                    PROT_READ,
+                    MAP_PRIVATE,
+                    file.as_raw_fd(),
+                    0,
+                )
+            };
+            if ptr as isize == -1 {
+                return Err("could not map file");
+            }
+            Ok(Self { ptr, len })
         }
 
-        fn execute(
-            opcodes: &[Self::OPCODE],
-            data: &mut [u8; DATA_LEN],
-            input: &[u8],
-            stdout: &mut std::io::StdoutLock<'static>,
-        ) {
-            let mut input = input.iter().copied();
-            let mut pc: usize = 0;
-            let mut dp: usize = 0;
-            loop {
-                let Some(opcode) = opcodes.get(pc) else { break };
-
-                match opcode {
-                    Opcode::Add(i) => {
-                        data[dp] = data[dp].wrapping_add(*i);
-                    }
-                    Opcode::BranchZero(i) => {
-                        if data[dp] == 0 {
-                            pc = *i as _;
-                        }
-                    }
-                    Opcode::BranchNotZero(i) => {
-                        if data[dp] != 0 {
-                            pc = *i as _;
-                        }
-                    }
-                    Opcode::Right(i) => {
-                        dp = dp.wrapping_add(*i as _);
-                    }
-                    Opcode::Dot => {
-                        let _ = stdout.write(&[data[dp]]);
-                    }
-                    Opcode::Comma => {
-                        data[dp as usize] = input.next().unwrap();
-                    }
-                }
-                pc += 1
+        pub fn bytes(&self) -> &[u8] {
+            if self.len == 0 {
+                return &[];
             }
+            unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
         }
     }
-}
-use shift_add_engine::*;
-mod shift_add_engine {
-    use super::{BasicOpcode, BfEngine, Write, DATA_LEN};
-    use std::collections::HashMap;
-    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
-    pub(super) enum Opcode {
-        /// Open
-        BranchZero(u16),
-        /// Close
-        BranchNotZero(u16),
-        AddRight(u8, i16), // inc, shift
-
-        SetRight(u8, i16), // set, shift
-        AddTo(i16), // introduce set?
-        SubTo(i16), // intrucude set?
-
-        Seek(i16),
-        //Add(u8),
-        //Right(i16),
-        Dot,
-        Comma,
-
-        Exit,
-    }
-
-    pub(super) struct ShiftAddEngine {}
-    impl BfEngine for ShiftAddEngine {
-        const NAME: &'static str = "Shift add";
-
-        type OPCODE = Opcode;
-
-        fn generate(
-            iter: impl Iterator<Item = BasicOpcode>,
-        ) -> Result<Vec<Self::OPCODE>, &'static str> {
-            let mut iter = iter;
-            let mut owned_buffer = Vec::new();
-            let buffer = &mut owned_buffer;
-            use Opcode::*;
-            let mut open_stack: Vec<usize> = Vec::new();
-            loop {
-                let Some(opcode) = iter.next() else { break };
-
-                match opcode {
-                    BasicOpcode::Add => buffer.push(AddRight(1 as _, 0 as _)),
-                    BasicOpcode::Sub => buffer.push(AddRight(-1 as _, 0 as _)),
-                    BasicOpcode::Right => buffer.push(AddRight(0 as _, 1 as _)),
-                    BasicOpcode::Left => buffer.push(AddRight(0 as _, -1 as _)),
-                    BasicOpcode::Open => {
-                        open_stack.push(buffer.len());
-                        buffer.push(BranchZero(0));
-                    }
-                    BasicOpcode::Close => {
-                        let other = open_stack.pop().ok_or("unbalanced brackets: extra ]")?;
-                        let this = buffer.len();
-                        buffer[other] = BranchZero(this.try_into().unwrap());
-
-                        match &buffer[..] {
-                            &[.., BranchZero(_), AddRight(_, 0)] => {
-                                buffer.truncate(buffer.len() - 2);
-                                buffer.push(SetRight(0, 0));
-                            }
-                            &[.., BranchZero(_), AddRight(255, x), AddRight(1, y)]
-                                if -x == y && x != 0 =>
-                            {
-                                buffer.truncate(buffer.len() - 3);
-                                buffer.push(AddTo(x));
-                            }
-                            &[.., BranchZero(_), AddRight(255, x), AddRight(255, y)]
-                                if -x == y && x != 0 =>
-                            {
-                                buffer.truncate(buffer.len() - 3);
-                                buffer.push(SubTo(x));
-                            }
-                            &[.., BranchZero(_), AddRight(_, x)] => {
-                                buffer.truncate(buffer.len() - 2);
-                                buffer.push(Seek(x));
-                            }
-                            _ => {
-                                buffer.push(BranchNotZero(other.try_into().unwrap()));
-                            }
-                        }
-                    }
-                    BasicOpcode::Dot => buffer.push(Dot),
-                    BasicOpcode::Comma => buffer.push(Comma),
-                }
 
-                let mut redundant_code_found = true;
+    impl Drop for Mapping {
+        fn drop(&mut self) {
+            if self.len != 0 {
+                unsafe { munmap(self.ptr, self.len) };
+            }
+        }
+    }
 
-                match &mut buffer[..] {
-                    [.., AddRight(curr_add, curr_shift @ 0) | SetRight(curr_add, curr_shift @ 0), AddRight(add, shift)] => {
-                        *curr_add += *add;
-                        *curr_shift = *shift;
-                        let _ = buffer.pop();
-                    }
-                    [.., AddRight(_, curr_shift) | SetRight(_, curr_shift), AddRight(0, shift)] => {
-                        *curr_shift += *shift;
-                        let _ = buffer.pop();
-                    }
-                    _ => (),
-                }
+    /// A `DATA_LEN` byte file mapped read-write, so writes to the tape land in
+    /// the file.
+    pub struct TapeFile {
+        ptr: *mut u8,
+    }
 
-                match &mut buffer[..] {
-                    &mut [.., AddRight(_, 0), a @ SetRight(0, 0)] => {
-                        buffer.truncate(buffer.len() - 2);
-                        buffer.push(a);
-                    }
-                    &mut [.., SetRight(0, 0), a @ AddTo(_)] => {
-                        buffer.truncate(buffer.len() - 2);
-                        buffer.push(a);
-                        panic!();
-                    }
-                    &mut [.., AddRight(0, 0)] => {
-                        let _ = buffer.pop();
-                        panic!();
-                    }
-                    _ => redundant_code_found = false,
-                }
-                if redundant_code_found {
-                    println!("found redundant code");
-                }
+    impl TapeFile {
+        /// Creates `path`, or truncates it, to hold exactly one tape.
+        pub fn create(path: &str) -> Result<Self, &'static str> {
+            let file = File::options()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)
+                .map_err(|_| "could not create tape file")?;
+            file.set_len(DATA_LEN as u64)
+                .map_err(|_| "could not size tape file")?;
+            let ptr = unsafe {
+                mmap(
+                    std::ptr::null_mut(),
+                    DATA_LEN,
+                    PROT_READ | PROT_WRITE,
+                    MAP_SHARED,
+                    file.as_raw_fd(),
+                    0,
+                )
+            };
+            if ptr as isize == -1 {
+                return Err("could not map tape file");
             }
-            buffer.push(Exit);
-            let mut patterns: HashMap<&str, usize> = HashMap::new();
-
-            macro_rules! pattern {
-                    ($i:ident, $pattern:pat $(if $guard:expr)? $(,)?) => {
-                        {
-                            let s = stringify!($pattern $(if $guard)?);
-                            match &buffer[..$i] {
-                                $pattern $(if $guard)? => *patterns.entry(s).or_default() += 1,
-                                _ => ()
-                            }
-                        }
-                    };
-                }
-            //for i in 0..buffer.len() {
-            //    //pattern!(i, &[.., AddTo(x), AddRight(0, y)] if x == y);
-            //    //pattern!(i, &[.., AddTo(_), AddRight(_, _)]);
-            //    //pattern!(i, &[.., AddTo(_), AddRight(0, _)]);
-            //    //pattern!(i, &[.., AddTo(_), AddRight(_, 0)]);
-            //    //pattern!(i, &[.., AddTo(_), AddRight(0, 0)]);
-            //    pattern!(i, &[.., Clear, AddRight(_, _)]);
-            //    pattern!(i, &[.., Clear, AddRight(0, _)]);
-            //    pattern!(i, &[.., Clear, AddRight(_, 0)]);
-            //    pattern!(i, &[.., Clear, AddRight(0, 0)]);
-            //    pattern!(i, &[.., Clear]);
-            //}
-
-            dbg!(&patterns);
-
-            if !open_stack.is_empty() {
-                return Err("unbalanced brackets: extra [");
-            } else {
-                Ok(owned_buffer)
-            }
-        }
-
-        #[inline(never)]
-        fn execute(
-            opcodes: &[Self::OPCODE],
-            data: &mut [u8; DATA_LEN],
-            input: &[u8],
-            stdout: &mut std::io::StdoutLock<'static>,
-        ) {
-            let mut input = input.iter().copied();
-            let mut pc: usize = 0;
-            let mut dp: usize = 0;
-
-
-
-            //let mut profile: Vec<usize> = (0..opcodes.len()).map(|_| 0).collect();
-            loop {
-                //let Some(opcode) = opcodes.get(pc) else { break };
-                unsafe {
-                    let opcode = opcodes.get_unchecked(pc);
-                    //profile[pc] += 1;
-
-                    match opcode {
-                        Opcode::AddRight(a, i) => {
-                            *data.get_unchecked_mut(dp) = data.get_unchecked(dp).wrapping_add(*a);
-                            dp = dp.wrapping_add(*i as _) % DATA_LEN;
-                        }
-                        Opcode::BranchZero(i) => {
-                            if *data.get_unchecked(dp) == 0 {
-                                pc = *i as _;
-                            }
-                        }
-                        Opcode::BranchNotZero(i) => {
-                            if *data.get_unchecked(dp) != 0 {
-                                pc = *i as _;
-                            }
-                        }
-                        Opcode::Dot => {
-                            let _ = stdout.write(&[*data.get_unchecked(dp)]);
-                        }
-                        Opcode::Comma => {
-                            let Some(c) = input.next() else { break };
-                            *data.get_unchecked_mut(dp) = c;
-                        }
-                        Opcode::SetRight(a, i) => {
-                            *data.get_unchecked_mut(dp) = *a;
-                            dp = dp.wrapping_add(*i as _) % DATA_LEN;
-                            //*data.get_unchecked_mut(dp) = 0;
-                        }
-                        Opcode::AddTo(i) => {
-                            let to = ((dp.wrapping_add(*i as _)) as usize) % DATA_LEN;
-
-                            let tmp = *data.get_unchecked(dp);
-
-                            *data.get_unchecked_mut(to) = data.get_unchecked(to).wrapping_add(tmp);
-                            *data.get_unchecked_mut(dp) = 0;
-                        }
-                        Opcode::SubTo(i) => {
-                            let to = ((dp.wrapping_add(*i as _)) as usize) % DATA_LEN;
-
-                            let tmp = *data.get_unchecked(dp);
-
-                            *data.get_unchecked_mut(to) = data.get_unchecked(to).wrapping_sub(tmp);
-                            *data.get_unchecked_mut(dp) = 0;
-                        }
-                        Opcode::Seek(i) => {
-                            while *data.get_unchecked(dp) != 0 {
-                                dp = dp.wrapping_add(*i as _) % DATA_LEN;
-                            }
-                        }
-                        Opcode::Exit => {
-                            break;
-                        }
-                    }
-                }
-                pc += 1
-            }
-
-            //{
-            //    let mut increment = 0;
-            //    for (line, (el, count)) in opcodes.iter().zip(&profile).enumerate() {
-            //        if let Opcode::BranchNotZero(_) = el {
-            //            increment -= 1
-            //        }
-            //        print!("{line:>5}: {count:>8} ");
-            //        for _ in 0..increment {
-            //            print!("    ");
-            //        }
-            //        println!("{el:?}");
-            //        if let Opcode::BranchZero(_) = el {
-            //            increment += 1
-            //        }
-            //    }
-            //    dbg!(profile.iter().enumerate().max_by_key(|a| a.1));
-            //}
+            Ok(Self { ptr })
+        }
+
+        pub fn tape(&self) -> &[u8; DATA_LEN] {
+            unsafe { &*(self.ptr as *const [u8; DATA_LEN]) }
+        }
+
+        pub fn tape_mut(&mut self) -> &mut [u8; DATA_LEN] {
+            unsafe { &mut *(self.ptr as *mut [u8; DATA_LEN]) }
+        }
+    }
+
+    impl Drop for TapeFile {
+        fn drop(&mut self) {
+            unsafe { munmap(self.ptr, DATA_LEN) };
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{Mapping, TapeFile};
+        use bf_interpreter::{compile_from_str, BfEngine, RunConfig, ShiftAddEngine, DATA_LEN};
+        use std::fs::File;
+
+        /// A file under the temp dir, removed again when dropped.
+        struct TempFile(std::path::PathBuf);
+
+        impl TempFile {
+            fn new(name: &str) -> Self {
+                let name = format!("bf_interpreter_{}_{name}", std::process::id());
+                Self(std::env::temp_dir().join(name))
+            }
+        }
+
+        impl Drop for TempFile {
+            fn drop(&mut self) {
+                let _ = std::fs::remove_file(&self.0);
+            }
+        }
+
+        #[test]
+        fn maps_the_whole_file() {
+            let file = TempFile::new("mapped.bf");
+            let src: Vec<u8> = b"+[>,.<]".iter().copied().cycle().take(100_000).collect();
+            std::fs::write(&file.0, &src).unwrap();
+            let mapping = Mapping::new(&File::open(&file.0).unwrap()).unwrap();
+            assert!(mapping.bytes() == src);
+            // Still mapped after the file is closed and removed.
+            drop(file);
+            assert!(mapping.bytes() == src);
+        }
+
+        #[test]
+        fn maps_an_empty_file() {
+            let file = TempFile::new("empty.bf");
+            std::fs::write(&file.0, b"").unwrap();
+            let mapping = Mapping::new(&File::open(&file.0).unwrap()).unwrap();
+            assert_eq!(mapping.bytes(), b"");
+        }
+
+        #[test]
+        fn tape_file_holds_the_final_tape() {
+            let file = TempFile::new("tape");
+            std::fs::write(&file.0, b"left over from before").unwrap();
+            let path = file.0.to_str().unwrap();
+            let mut tape = TapeFile::create(path).unwrap();
+            assert!(tape.tape().iter().all(|&cell| cell == 0));
+
+            let opcodes = compile_from_str::<ShiftAddEngine>("+++>++>,[->+<]<<<-").unwrap();
+            let config = RunConfig::default();
+            let mut output = Vec::new();
+            ShiftAddEngine::execute(
+                &opcodes,
+                tape.tape_mut(),
+                &mut &b"x"[..],
+                &mut output,
+                &config,
+            );
+            // Visible through the file while still mapped, and after. The
+            // `<<<-` wrapped around to the last cell.
+            let check = || {
+                let saved = std::fs::read(&file.0).unwrap();
+                assert_eq!(saved.len(), DATA_LEN);
+                assert_eq!(saved[..4], [3, 2, 0, b'x']);
+                assert_eq!(saved[DATA_LEN - 1], 255);
+            };
+            check();
+            drop(tape);
+            check();
+        }
+    }
+}
+
+/// Ctrl-C stops the running program at the next check instead of killing the
+/// process, so the profile and stats collected so far still get printed.
+#[cfg(all(feature = "signal", unix))]
+mod interrupt {
+    use bf_interpreter::{Observer, DATA_LEN};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    const SIGINT: i32 = 2;
+    const SIG_DFL: usize = 0;
+    /// How many opcodes run between checks of the flag.
+    const CHECK_INTERVAL: u32 = 1 << 16;
+
+    static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+    extern "C" {
+        fn signal(signum: i32, handler: usize) -> usize;
+    }
+
+    extern "C" fn handle_sigint(_: i32) {
+        INTERRUPTED.store(true, Ordering::Relaxed);
+        // A second Ctrl-C kills the process as usual.
+        unsafe { signal(SIGINT, SIG_DFL) };
+    }
+
+    #[derive(Default)]
+    pub struct Watch {
+        countdown: u32,
+    }
+
+    impl Watch {
+        pub fn install() -> Self {
+            unsafe { signal(SIGINT, handle_sigint as extern "C" fn(i32) as usize) };
+            Self::default()
+        }
+    }
+
+    impl Observer for Watch {
+        fn pause(&mut self, _data: &[u8; DATA_LEN], _dp: usize) -> bool {
+            self.countdown = self.countdown.wrapping_add(1);
+            self.countdown.is_multiple_of(CHECK_INTERVAL) && INTERRUPTED.load(Ordering::Relaxed)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use bf_interpreter::{compile_from_str, BfEngine, ExitStatus, RunConfig, ShiftAddEngine};
+
+        extern "C" {
+            fn raise(signum: i32) -> i32;
+        }
+
+        /// One test, since the flag and the handler are process-wide.
+        #[test]
+        fn sigint_stops_an_endless_program() {
+            let mut watch = Watch::install();
+            let data = [0; DATA_LEN];
+            assert!((0..2 * CHECK_INTERVAL).all(|_| !watch.pause(&data, 0)));
+
+            assert_eq!(unsafe { raise(SIGINT) }, 0);
+            assert!(INTERRUPTED.load(Ordering::Relaxed));
+            let opcodes = compile_from_str::<ShiftAddEngine>("+[>+<]").unwrap();
+            let mut data = Box::new([0; DATA_LEN]);
+            let status = ShiftAddEngine::execute_observed(
+                &opcodes,
+                &mut data,
+                &mut &b""[..],
+                &mut Vec::new(),
+                &RunConfig::default(),
+                &mut watch,
+            );
+            assert_eq!(status, ExitStatus::Interrupted);
+
+            // The handler put the default back for a second Ctrl-C.
+            let previous = unsafe { signal(SIGINT, SIG_DFL) };
+            assert_eq!(previous, SIG_DFL);
         }
     }
 }
@@ -0,0 +1,105 @@
+//! Runs the interpreter binary over pipes, the way it's used interactively.
+
+use std::io::{Read, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+/// How long to wait for a byte before deciding the interpreter is stuck.
+const PATIENCE: Duration = Duration::from_secs(10);
+
+/// The interpreter running a program, with its stdin and stdout piped.
+struct Piped {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    /// Stdout a byte at a time, read on another thread so a stuck
+    /// interpreter fails the test instead of hanging it.
+    output: Receiver<u8>,
+}
+
+impl Piped {
+    /// Starts the interpreter with `args` on `src`, written to a file named
+    /// after `name`.
+    fn spawn(name: &str, src: &str, args: &[&str]) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("bf_interpreter_{}_{name}.bf", std::process::id()));
+        std::fs::write(&path, src).unwrap();
+        let mut child = Command::new(env!("CARGO_BIN_EXE_bf_interpreter"))
+            .arg("--quiet")
+            .args(args)
+            .arg(&path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let stdin = child.stdin.take();
+        let mut stdout = child.stdout.take().unwrap();
+        let (send, output) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut byte = [0];
+            while let Ok(1) = stdout.read(&mut byte) {
+                if send.send(byte[0]).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            child,
+            stdin,
+            output,
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        let stdin = self.stdin.as_mut().unwrap();
+        stdin.write_all(bytes).unwrap();
+        stdin.flush().unwrap();
+    }
+
+    /// Waits for `expected` to come out, without closing stdin first.
+    fn expect(&self, expected: &[u8]) {
+        for (i, &byte) in expected.iter().enumerate() {
+            let got = self.output.recv_timeout(PATIENCE);
+            assert_eq!(got, Ok(byte), "byte {i} of {expected:?}");
+        }
+    }
+
+    /// Closes stdin and checks the interpreter exits cleanly with nothing
+    /// more to say.
+    fn finish(mut self) {
+        drop(self.stdin.take());
+        let rest = self.output.recv_timeout(PATIENCE);
+        assert_eq!(rest, Err(RecvTimeoutError::Disconnected));
+        assert!(self.child.wait().unwrap().success());
+    }
+}
+
+#[test]
+fn cat_echoes_as_input_arrives() {
+    let mut cat = Piped::spawn("cat", ",[.,]", &[]);
+    for line in [&b"hello\n"[..], b"world\n"] {
+        cat.write(line);
+        cat.expect(line);
+    }
+    cat.finish();
+}
+
+#[test]
+fn unbuffered_output_keeps_up_without_newlines() {
+    // Prints `>` as a prompt, then echoes.
+    let src = "++++++++[>++++++++<-]>--.,[.,]";
+    for buffering in ["none", "block", "line"] {
+        let mut cat = Piped::spawn(
+            &format!("prompt_{buffering}"),
+            src,
+            &["--output-buffering", buffering],
+        );
+        cat.expect(b">");
+        for bytes in [&b"a"[..], b"bc", b"d"] {
+            cat.write(bytes);
+            cat.expect(bytes);
+        }
+        cat.finish();
+    }
+}